@@ -1,5 +1,4 @@
 use chrono::Duration;
-use tokio::time;
 
 use api_key_pool::*;
 
@@ -16,21 +15,17 @@ async fn main() {
     let api3 = APIKey::new("3", pol);
 
     // Create the APIKeyPool.
-    let mut pool = APIKeyPool::new();
+    let pool = APIKeyPool::new();
     pool.add_key(api1).await;
     pool.add_key(api2).await;
     pool.add_key(api3).await;
 
     // Simulate 20 requests.
-    let mut ctr = 0;
-    while ctr < 20 {
-        // Use the APIKey if available (according to its respective RateLimitPolicy) or sleep.
-        if let Some(key) = pool.poll_for_key().await {
-            println!("{}", key);
-            ctr += 1;
-        } else {
-            println!("Have to sleep.");
-            time::sleep(time::Duration::from_millis(500)).await;
-        }
+    for _ in 0..20 {
+        // Wait until an APIKey is available (according to its respective RateLimitPolicy).
+        let Some(key) = pool.wait_for_key().await else {
+            break;
+        };
+        println!("{}", key);
     }
 }
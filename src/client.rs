@@ -0,0 +1,207 @@
+//! An HTTP client that signs each request with an API key from a pool.
+
+use std::fmt;
+
+use reqwest::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{Client, Request, Response, StatusCode};
+
+use crate::provider::{self, KeyProvider};
+use crate::{APIKeyPool, CallError, PoolError};
+
+/// How long an API key is parked after a `429 Too Many Requests` response without a
+/// `Retry-After` header, in seconds.
+const DEFAULT_COOLDOWN_SECS: i64 = 1;
+
+/// An HTTP client that waits for an API key from a pool before sending each request, and injects
+/// it into a header of the request.
+///
+/// When the provider answers with `429 Too Many Requests`, the API key is parked for the cooldown
+/// given by the response's `Retry-After` header, as in [`APIKeyPool::report_failure`], and the
+/// request is sent again with the next available API key.
+///
+/// The API keys are drawn from an [`APIKeyPool`] by default, but any [`KeyProvider`] can stand in
+/// for it, including an `Arc<dyn KeyProvider>`.
+///
+/// # Example
+///
+/// ```no_run
+/// use chrono::Duration;
+/// use reqwest::header::AUTHORIZATION;
+///
+/// use api_key_pool::*;
+///
+/// # async fn run() -> Result<(), ClientError> {
+/// let pool = key_pool!(
+///     policy = RateLimitPolicy::new(5, Duration::seconds(1));
+///     "k1",
+///     "k2",
+/// );
+/// let client = PoolBackedClient::new(reqwest::Client::new(), pool, AUTHORIZATION)
+///     .with_prefix("Bearer ");
+/// let request = client.client().get("https://example.com").build()?;
+/// let response = client.send(request).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct PoolBackedClient<P: KeyProvider = APIKeyPool> {
+    /// The HTTP client that sends the requests.
+    client: Client,
+    /// The pool the API keys are drawn from.
+    pool: P,
+    /// The header the API key is injected into.
+    header: HeaderName,
+    /// The text put before the API key in the header, such as `Bearer `.
+    prefix: String,
+}
+
+impl<P: KeyProvider> PoolBackedClient<P> {
+    /// Returns a client that injects API keys from the pool into the given header, as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - the HTTP client that sends the requests.
+    /// * `pool` - the pool the API keys are drawn from.
+    /// * `header` - the header the API key is injected into.
+    pub fn new(client: Client, pool: P, header: HeaderName) -> Self {
+        Self {
+            client,
+            pool,
+            header,
+            prefix: String::new(),
+        }
+    }
+
+    /// Returns the client with text put before the API key in the header, such as `Bearer ` for
+    /// an `Authorization` header.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - the text put before the API key.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Returns the HTTP client, for building requests to send.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns the pool the API keys are drawn from.
+    pub fn pool(&self) -> &P {
+        &self.pool
+    }
+
+    /// Waits until an API key is available, injects it into the request, and sends the request.
+    ///
+    /// A `429 Too Many Requests` response reports the API key as rate-limited, as in
+    /// [`KeyProvider::report_failure`], and sends the request again with the next available API
+    /// key, as in [`APIKeyPool::with_key`], unless the request's body is a stream that cannot be
+    /// sent twice, in which case the response is returned as is. Any other response is returned
+    /// as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - the request to be sent.
+    pub async fn send(&self, request: Request) -> Result<Response, ClientError> {
+        let mut original = Some(request);
+        provider::with_key(&self.pool, |key| {
+            // Keep the original for retries for as long as it can be copied.
+            let mut request = match original.as_ref().and_then(Request::try_clone) {
+                Some(request) => request,
+                None => original
+                    .take()
+                    .expect("a request that cannot be copied is only sent once"),
+            };
+            let retryable = original.is_some();
+            async move {
+                let mut value = HeaderValue::from_str(&format!("{}{}", self.prefix, key))
+                    .map_err(|_| CallError::Failed(ClientError::InvalidKey))?;
+                // Keep the API key out of debug output and HTTP/2 header compression.
+                value.set_sensitive(true);
+                request.headers_mut().insert(self.header.clone(), value);
+                let response = self
+                    .client
+                    .execute(request)
+                    .await
+                    .map_err(|error| CallError::Failed(ClientError::Request(error)))?;
+                if retryable && response.status() == StatusCode::TOO_MANY_REQUESTS {
+                    return Err(CallError::RateLimited {
+                        retry_after: retry_after(&response),
+                    });
+                }
+                Ok(response)
+            }
+        })
+        .await
+    }
+}
+
+impl<P: KeyProvider> fmt::Debug for PoolBackedClient<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolBackedClient")
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns how long the API key should be parked for after a `429 Too Many Requests` response,
+/// read from the response's `Retry-After` header as a number of seconds.
+///
+/// # Arguments
+///
+/// * `response` - the `429 Too Many Requests` response.
+fn retry_after(response: &Response) -> chrono::Duration {
+    let secs = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .unwrap_or(DEFAULT_COOLDOWN_SECS);
+    chrono::Duration::seconds(secs.max(0))
+}
+
+/// An error returned when a request cannot be sent with an API key from a pool.
+#[derive(Debug)]
+pub enum ClientError {
+    /// No API key could be acquired, because the pool was shut down, or every attempt was
+    /// rate-limited, as reported by [`PoolError::AllBusy`].
+    Pool(PoolError),
+    /// An API key could not be put into a header, because it has characters a header cannot hold.
+    InvalidKey,
+    /// The request could not be built or sent.
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Pool(error) => error.fmt(f),
+            ClientError::InvalidKey => write!(f, "the API key is not a valid header value"),
+            ClientError::Request(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Pool(error) => Some(error),
+            ClientError::Request(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<PoolError> for ClientError {
+    fn from(error: PoolError) -> Self {
+        ClientError::Pool(error)
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> Self {
+        ClientError::Request(error)
+    }
+}
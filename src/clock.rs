@@ -0,0 +1,121 @@
+//! Sources of the current time, which API key pools use for all of their rate limit math.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use chrono::{DateTime, Utc};
+
+/// A source of the current time.
+///
+/// An API key pool reads the time from its clock whenever it checks or records the use of an API
+/// key, so a test can drive readiness by substituting a [`MockClock`] for the [`SystemClock`].
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A clock that reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that follows the Tokio runtime's timer, anchored to the system time when it is created.
+///
+/// API key pools sleep on the runtime's timer while they wait for an API key, so with
+/// `tokio::time::pause` only this clock moves in step with those sleeps, and tests can wait for
+/// API keys in virtual time.
+#[derive(Clone, Copy, Debug)]
+pub struct TokioClock {
+    /// The system time when the clock was created.
+    start: DateTime<Utc>,
+    /// The runtime's time when the clock was created.
+    instant: tokio::time::Instant,
+}
+
+impl TokioClock {
+    /// Returns a clock that starts at the system time and follows the runtime's timer from then on.
+    pub fn new() -> Self {
+        Self {
+            start: Utc::now(),
+            instant: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for TokioClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for TokioClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed =
+            chrono::Duration::from_std(self.instant.elapsed()).unwrap_or(chrono::TimeDelta::MAX);
+        self.start
+            .checked_add_signed(elapsed)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+/// A clock that only moves when it is told to, for deterministic tests.
+///
+/// A mock clock does not follow the runtime's timer, so waits such as
+/// [`crate::APIKeyPool::wait_for_key`] only resolve once the clock is advanced past the API key's
+/// window.
+///
+/// Clones of a mock clock share the same time, so a test can keep one clone to advance while the
+/// pool reads another.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    /// The current time of the clock.
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    /// Returns a mock clock set to the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the time the clock starts at.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Moves the clock forward.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - how far to move the clock.
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+
+    /// Sets the clock to the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the new time of the clock.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+}
+
+impl Default for MockClock {
+    /// Returns a mock clock set to the system time.
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,160 @@
+//! An index of the API keys of a pool, ordered by when each API key is next available.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use chrono::{DateTime, Utc};
+
+use crate::APIKey;
+
+/// An index of the API keys of a pool, ordered by when each API key is next available.
+///
+/// Each entry is a lower bound on when its API key is next available. Uses and cooldowns only ever
+/// push an API key's availability later, so the soonest entries are re-checked against their API
+/// keys and pushed back if needed. An API key that is warming up can become available sooner as
+/// its count grows, so its entry is when it would be available at its full count. Changes that can
+/// make an API key available sooner, or that move API keys around in the pool, mark the index
+/// dirty so that it is rebuilt before its next use.
+pub(crate) struct ReadyIndex {
+    /// Min-heap of (next available time, position in the pool, generation) entries.
+    entries: BinaryHeap<Reverse<(DateTime<Utc>, usize, u64)>>,
+    /// The generation of the live entry for each position in the pool; other entries are stale.
+    generations: Vec<u64>,
+    /// A lower bound on when the API key at each position in the pool is next available, which is
+    /// no later than its live entry, or `None` if it has no entry because it is disabled.
+    bounds: Vec<Option<DateTime<Utc>>>,
+    /// Whether the index must be rebuilt before its next use.
+    dirty: bool,
+}
+
+impl ReadyIndex {
+    /// Returns an index that is rebuilt on first use.
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: BinaryHeap::new(),
+            generations: Vec::new(),
+            bounds: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    /// Marks the index to be rebuilt before its next use.
+    pub(crate) fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Rebuilds the index from scratch.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_keys` - the locked API keys of the pool.
+    /// * `now` - the current time.
+    async fn rebuild(&mut self, api_keys: &[APIKey], now: DateTime<Utc>) {
+        self.entries.clear();
+        self.generations = vec![0; api_keys.len()];
+        self.bounds = vec![None; api_keys.len()];
+        for (index, key) in api_keys.iter().enumerate() {
+            if let Some(at) = key.next_available_bound(now).await {
+                self.entries.push(Reverse((at, index, 0)));
+                self.bounds[index] = Some(at);
+            }
+        }
+        self.dirty = false;
+    }
+
+    /// Rebuilds the index if it is marked to be rebuilt.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_keys` - the locked API keys of the pool.
+    /// * `now` - the current time.
+    pub(crate) async fn refresh(&mut self, api_keys: &[APIKey], now: DateTime<Utc>) {
+        if self.dirty {
+            self.rebuild(api_keys, now).await;
+        }
+    }
+
+    /// Checks to see if the API key at the given position may be available at the given time,
+    /// going by its entry alone. An API key that may not be is certainly not available.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - the position of the API key in the pool.
+    /// * `now` - the current time.
+    pub(crate) fn may_be_ready(&self, index: usize, now: DateTime<Utc>) -> bool {
+        // An index that is yet to be rebuilt knows nothing, so it rules nothing out.
+        self.dirty || self.bounds[index].is_some_and(|at| at <= now)
+    }
+
+    /// Records when the API key at the given position is next available, after it has been used.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - the position of the API key in the pool.
+    /// * `at` - when the API key is next available, or `None` if it is disabled.
+    pub(crate) fn update(&mut self, index: usize, at: Option<DateTime<Utc>>) {
+        if self.dirty {
+            return;
+        }
+        self.generations[index] += 1;
+        self.bounds[index] = at;
+        if let Some(at) = at {
+            self.entries
+                .push(Reverse((at, index, self.generations[index])));
+        }
+    }
+
+    /// Returns the position of the soonest available API key along with when it is available, or
+    /// `None` if no API key is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_keys` - the locked API keys of the pool.
+    /// * `now` - the current time.
+    pub(crate) async fn soonest(
+        &mut self,
+        api_keys: &[APIKey],
+        now: DateTime<Utc>,
+    ) -> Option<(usize, DateTime<Utc>)> {
+        if self.dirty {
+            self.rebuild(api_keys, now).await;
+        }
+        let mut soonest: Option<(usize, DateTime<Utc>)> = None;
+        let mut checked = Vec::new();
+        while let Some(Reverse((at, index, generation))) = self.entries.pop() {
+            if generation != self.generations[index] {
+                continue;
+            }
+            // No API key is available before its entry, nor before now, so once an API key is
+            // found that is available by this entry, none of the rest can beat it.
+            if soonest.is_some_and(|(_, soonest)| soonest <= at.max(now)) {
+                self.entries.push(Reverse((at, index, generation)));
+                break;
+            }
+            // The entry is only a lower bound, so check it against the API key itself. A disabled
+            // API key is dropped, and added back when the index is rebuilt. An API key that is
+            // already available keeps its entry, so that looking does not reorder ties.
+            let Some(actual) = api_keys[index].next_available(now).await else {
+                self.bounds[index] = None;
+                continue;
+            };
+            let entry = if actual <= now {
+                at
+            } else {
+                api_keys[index]
+                    .next_available_bound(now)
+                    .await
+                    .unwrap_or(actual)
+            };
+            // Acquires can read the clock out of order, so an API key that is available now may
+            // have an entry from a later time; its bound is what was just checked.
+            self.bounds[index] = Some(entry.min(actual));
+            checked.push(Reverse((entry, index, generation)));
+            if soonest.is_none_or(|(_, soonest)| actual < soonest) {
+                soonest = Some((index, actual));
+            }
+        }
+        self.entries.extend(checked);
+        soonest
+    }
+}
@@ -6,7 +6,6 @@
 //!
 //! ```
 //! use chrono::Duration;
-//! use tokio::time;
 //!
 //! use api_key_pool::*;
 //!
@@ -23,145 +22,4076 @@
 //!     let api3 = APIKey::new("3", pol);
 //!
 //!     // Create the APIKeyPool.
-//!     let mut pool = APIKeyPool::new();
+//!     let pool = APIKeyPool::new();
 //!     pool.add_key(api1).await;
 //!     pool.add_key(api2).await;
 //!     pool.add_key(api3).await;
 //!
 //!     // Simulate 20 requests.
-//!     let mut ctr = 0;
-//!     while ctr < 20 {
-//!         // Use the APIKey if available (according to its respective RateLimitPolicy) or sleep.
-//!         if let Some(key) = pool.poll_for_key().await {
-//!             println!("{}", key);
-//!             ctr += 1;
-//!         } else {
-//!             println!("Have to sleep.");
-//!             time::sleep(time::Duration::from_millis(500)).await;
-//!         }
+//!     for _ in 0..20 {
+//!         // Wait until an APIKey is available (according to its respective RateLimitPolicy).
+//!         let Some(key) = pool.wait_for_key().await else {
+//!             break;
+//!         };
+//!         println!("{}", key);
 //!     }
 //! }
 //! ```
 
 
-use std::cmp::Reverse;
-use std::collections::BinaryHeap;
-use std::sync::Arc;
+#[cfg(feature = "reqwest")]
+mod client;
+mod clock;
+mod index;
+mod macros;
+mod provider;
+#[cfg(feature = "stream")]
+mod stream;
+mod times;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError};
 
 use chrono::{DateTime, Utc};
-use tokio::sync::Mutex;
+use rand::rngs::SmallRng;
+use rand::{RngExt, SeedableRng};
+use tokio::sync::{watch, Mutex, MutexGuard, Notify, RwLock};
+use tokio::task::JoinSet;
+use tokio::time;
+
+#[cfg(feature = "reqwest")]
+pub use client::{ClientError, PoolBackedClient};
+pub use clock::{Clock, MockClock, SystemClock, TokioClock};
+use index::ReadyIndex;
+pub use provider::{BoxFuture, KeyProvider};
+use times::Times;
+
+/// A pool of API keys.
+///
+/// Cloning an API key pool is cheap, and every clone shares the same API keys and rate-limit state,
+/// so a pool can be handed to many tasks without wrapping it in an [`Arc`].
+///
+/// Every method that changes a pool, from adding API keys to handing them out, takes `&self`, so a
+/// shared pool needs no outer lock.
+///
+/// With the `metrics` feature, each look at an API key's usage history to see how available it is
+/// increments the `api_key_pool_readiness_checks_total` counter.
+#[derive(Clone)]
+pub struct APIKeyPool {
+    /// Collection holding the API keys, which inspections and acquires lock for reading and changes
+    /// lock for writing. Acquires only hold the lock while choosing an API key.
+    api_keys: Arc<RwLock<Vec<APIKey>>>,
+    /// The strategy used to choose among available API keys.
+    strategy: SelectionStrategy,
+    /// State used to choose among available API keys, which is always locked after `api_keys`.
+    selector: Arc<Mutex<Selector>>,
+    /// Callback called with the code of every API key handed out.
+    acquire_callback: Arc<std::sync::RwLock<Option<AcquireCallback>>>,
+    /// The pool consulted when none of this pool's API keys is available.
+    fallback: Option<Box<APIKeyPool>>,
+    /// The source of the current time.
+    clock: Arc<dyn Clock>,
+    /// Whether the pool has been shut down, which waiting acquires watch for.
+    shutdown: Arc<watch::Sender<bool>>,
+    /// The most API keys the pool holds, if limited.
+    max_keys: Option<usize>,
+    /// Wakes waiting acquires when API keys become available other than by time passing, such as
+    /// when an API key is added or enabled. Time passing is covered by each waiter's own timer.
+    freed: Arc<Notify>,
+}
+
+impl fmt::Debug for APIKeyPool {
+    /// Formats the API key pool with every API key code masked. If the API keys are locked for
+    /// writing, they are left out.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("APIKeyPool");
+        match self.api_keys.try_read() {
+            Ok(api_keys) => debug.field("api_keys", &*api_keys),
+            Err(_) => debug.field("api_keys", &format_args!("<locked>")),
+        };
+        debug
+            .field("strategy", &self.strategy)
+            .field("fallback", &self.fallback)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An API key that has been handed out by a pool.
+struct Acquired {
+    /// The position of the API key in the pool it was handed out by.
+    index: usize,
+    /// The API key code.
+    key: String,
+    /// The number of guards held for the API key.
+    in_flight: Arc<AtomicUsize>,
+    /// The metadata attached to the API key, if any.
+    meta: Option<Arc<serde_json::Value>>,
+    /// Wakes the waiting acquires of the pool the API key was handed out by, and of each pool that
+    /// fell back to it.
+    freed: Vec<Arc<Notify>>,
+    /// How many more times the API key can be used right now, after this use.
+    remaining: usize,
+}
+
+/// What an acquire asks of the API key it is handed.
+#[derive(Clone, Copy, Default)]
+struct AcquireRequest<'a> {
+    /// The session whose API key is preferred, if any.
+    session_id: Option<&'a str>,
+    /// The tag that the API key must have, if any.
+    tag: Option<&'a str>,
+    /// The codes that the API key must have one of, if restricted.
+    allowed: Option<&'a [&'a str]>,
+    /// How many more times the API key must be usable right now, if more than once.
+    min_capacity: usize,
+    /// Whether the API key is held by a guard, counting as in flight until the guard is dropped.
+    hold: bool,
+    /// The id of the request the API key is for, passed to the acquire callback, if any.
+    request_id: Option<&'a str>,
+}
+
+/// A check called with the code of an API key, which returns whether the API key can be chosen.
+type HealthCheck = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// A callback called with the code of every API key handed out, along with the id of the request
+/// it was handed out for, if any.
+type AcquireCallback = Arc<dyn Fn(&str, Option<&str>) + Send + Sync>;
+
+impl Default for APIKeyPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<APIKey> for APIKeyPool {
+    /// Returns an API key pool holding the given API keys, without locking.
+    fn from_iter<I: IntoIterator<Item = APIKey>>(iter: I) -> Self {
+        Self::builder().with_keys(iter).build()
+    }
+}
+
+impl From<HashMap<String, RateLimitPolicy>> for APIKeyPool {
+    /// Returns an API key pool with an API key for each code, governed by its policy.
+    ///
+    /// The pool order follows the iteration order of the map, which is unspecified. Convert from a
+    /// [`BTreeMap`] instead for a reproducible pool order.
+    fn from(keys: HashMap<String, RateLimitPolicy>) -> Self {
+        keys.iter()
+            .map(|(key, &policy)| APIKey::new(key, policy))
+            .collect()
+    }
+}
+
+impl From<BTreeMap<String, RateLimitPolicy>> for APIKeyPool {
+    /// Returns an API key pool with an API key for each code, governed by its policy, in order of
+    /// the codes.
+    fn from(keys: BTreeMap<String, RateLimitPolicy>) -> Self {
+        keys.iter()
+            .map(|(key, &policy)| APIKey::new(key, policy))
+            .collect()
+    }
+}
+
+impl APIKeyPool {
+    /// Returns an empty API key pool.
+    pub fn new() -> Self {
+        Self::with_strategy(SelectionStrategy::default())
+    }
+
+    /// Returns an empty API key pool wrapped in an [`Arc`], ready to be shared between tasks.
+    ///
+    /// Every method works through `&self`, so the pool can be used from each task through a clone
+    /// of the [`Arc`]. Cloning the pool itself shares its state in the same way.
+    pub fn shared() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    /// Returns a builder for an API key pool.
+    pub fn builder() -> APIKeyPoolBuilder {
+        APIKeyPoolBuilder::new()
+    }
+
+    /// Returns an empty API key pool that chooses among available API keys with the given strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - the selection strategy.
+    pub fn with_strategy(strategy: SelectionStrategy) -> Self {
+        Self {
+            api_keys: Arc::new(RwLock::new(Vec::new())),
+            strategy,
+            selector: Arc::new(Mutex::new(Selector::new(None))),
+            acquire_callback: Arc::new(std::sync::RwLock::new(None)),
+            fallback: None,
+            clock: Arc::new(SystemClock),
+            shutdown: Arc::new(watch::Sender::new(false)),
+            max_keys: None,
+            freed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns an empty API key pool that holds at most the given number of API keys.
+    ///
+    /// When adding an API key would take the pool over the cap, the least recently used API key is
+    /// evicted first, with API keys that have never been used counting as the least recently
+    /// used. This suits pools of dynamically discovered API keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_keys` - the most API keys the pool holds.
+    pub fn with_capacity(max_keys: usize) -> Self {
+        Self {
+            max_keys: Some(max_keys),
+            ..Self::new()
+        }
+    }
+
+    /// Returns the API key pool with a fallback pool, which is only consulted when none of this
+    /// pool's API keys is available.
+    ///
+    /// API keys handed out by the fallback pool are still subject to its own rate limits, and its
+    /// own acquire callback is called for them as well. Dropping a guard for such an API key wakes
+    /// the waiting acquires of both pools.
+    ///
+    /// # Arguments
+    ///
+    /// * `fallback` - the pool to fall back to.
+    pub fn with_fallback(mut self, fallback: APIKeyPool) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Returns an API key pool with an API key for each environment variable whose name starts
+    /// with the given prefix.
+    ///
+    /// API keys are added in order of their variable names, so that the pool order is reproducible.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - the prefix of the environment variable names, e.g. `MYAPP_KEY_`.
+    /// * `policy` - the rate limit policy governing each API key.
+    pub fn from_env(prefix: &str, policy: RateLimitPolicy) -> Self {
+        let mut vars: Vec<(String, String)> = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(name, _)| name.starts_with(prefix))
+            .collect();
+        vars.sort();
+        Self::builder()
+            .with_keys(vars.iter().map(|(_, value)| APIKey::new(value, policy)))
+            .build()
+    }
+
+    /// Adds an API key to an API key pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key to be added.
+    pub async fn add_key(&self, mut key: APIKey) {
+        key.clock = Arc::clone(&self.clock);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key = %mask(&key.key), "added API key");
+        let mut api_keys = self.api_keys.write().await;
+        self.make_room(&mut api_keys, 1).await;
+        api_keys.push(key);
+        self.trim(&mut api_keys);
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+    }
+
+    /// Adds several API keys to an API key pool at once, locking the pool only once.
+    ///
+    /// If the pool holds at most a number of API keys, as with [`APIKeyPool::with_capacity`], the
+    /// least recently used API keys are evicted to make room, and any of the given API keys beyond
+    /// the cap are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - the API keys to be added.
+    pub async fn add_keys(&self, keys: impl IntoIterator<Item = APIKey>) {
+        let keys: Vec<APIKey> = keys
+            .into_iter()
+            .map(|mut key| {
+                key.clock = Arc::clone(&self.clock);
+                key
+            })
+            .collect();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(count = keys.len(), "added API keys");
+        let mut api_keys = self.api_keys.write().await;
+        self.make_room(&mut api_keys, keys.len()).await;
+        api_keys.extend(keys);
+        self.trim(&mut api_keys);
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+    }
+
+    /// Adds an API key to an API key pool unless a key with the same code is already in it, and
+    /// returns whether the key was added.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key to be added.
+    pub async fn add_key_unique(&self, mut key: APIKey) -> bool {
+        let mut api_keys = self.api_keys.write().await;
+        if api_keys.iter().any(|k| k.key == key.key) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = %mask(&key.key), "API key already in pool");
+            return false;
+        }
+        key.clock = Arc::clone(&self.clock);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key = %mask(&key.key), "added API key");
+        self.make_room(&mut api_keys, 1).await;
+        api_keys.push(key);
+        self.trim(&mut api_keys);
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+        true
+    }
+
+    /// Moves every API key of another pool into this pool, keeping each API key's policy and usage
+    /// history.
+    ///
+    /// An API key whose code is already in this pool is rejected and logged, and stays in the other
+    /// pool. If this pool holds at most a number of API keys, as with
+    /// [`APIKeyPool::with_capacity`], the least recently used API keys are evicted to make room, and
+    /// any API keys beyond the cap stay in the other pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - the pool whose API keys are moved.
+    pub async fn merge(&self, other: APIKeyPool) {
+        let incoming = {
+            let mut other_keys = other.api_keys.write().await;
+            other.selector.lock().await.index.invalidate();
+            std::mem::take(&mut *other_keys)
+        };
+        let mut rejected = Vec::new();
+        {
+            let mut api_keys = self.api_keys.write().await;
+            let mut accepted: Vec<APIKey> = Vec::new();
+            for key in incoming {
+                if api_keys.iter().chain(&accepted).any(|k| k.key == key.key) {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(key = %mask(&key.key), "rejected duplicate API key in merge");
+                    rejected.push(key);
+                    continue;
+                }
+                accepted.push(key);
+            }
+            self.make_room(&mut api_keys, accepted.len()).await;
+            if let Some(max_keys) = self.max_keys {
+                let room = max_keys.saturating_sub(api_keys.len()).min(accepted.len());
+                rejected.extend(accepted.drain(room..));
+            }
+            for mut key in accepted {
+                key.clock = Arc::clone(&self.clock);
+                api_keys.push(key);
+            }
+            self.selector.lock().await.index.invalidate();
+            self.freed.notify_waiters();
+        }
+        if !rejected.is_empty() {
+            other.api_keys.write().await.extend(rejected);
+        }
+    }
+
+    /// Removes an API key from an API key pool, and returns whether a key was removed.
+    ///
+    /// If multiple API keys share the same code, only the first one is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key to be removed.
+    pub async fn remove_key(&self, key: &str) -> bool {
+        let mut api_keys = self.api_keys.write().await;
+        if let Some(index) = api_keys.iter().position(|k| k.key == key) {
+            self.remove_index(&mut api_keys, index).await;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = %mask(key), "removed API key");
+            return true;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key = %mask(key), "no API key to remove");
+        false
+    }
+
+    /// Removes the API key at the given position from an API key pool, and returns it, or returns
+    /// `None` if there is no API key at that position.
+    ///
+    /// Positions are as reported by [`APIKeyPool::acquire_indexed`]. The API keys after the removed
+    /// one shift down by one.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - the position of the API key to be removed.
+    pub async fn remove_at(&self, index: usize) -> Option<APIKey> {
+        let mut api_keys = self.api_keys.write().await;
+        if index >= api_keys.len() {
+            return None;
+        }
+        let removed = self.remove_index(&mut api_keys, index).await;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key = %mask(&removed.key), index, "removed API key");
+        Some(removed)
+    }
+
+    /// Removes the API key at the given position, keeping the round robin cursor on the API key it
+    /// pointed at.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_keys` - the write-locked API keys of the pool.
+    /// * `index` - the position of the API key to be removed, which must be in bounds.
+    async fn remove_index(&self, api_keys: &mut Vec<APIKey>, index: usize) -> APIKey {
+        let removed = api_keys.remove(index);
+        let mut selector = self.selector.lock().await;
+        if index < selector.next_index {
+            selector.next_index -= 1;
+        }
+        if selector.next_index >= api_keys.len() {
+            selector.next_index = 0;
+        }
+        selector.index.invalidate();
+        removed
+    }
+
+    /// Evicts the least recently used API keys until there is room for the given number of new
+    /// ones under the pool's cap, or no API key is left.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_keys` - the write-locked API keys of the pool.
+    /// * `incoming` - the number of API keys about to be added.
+    async fn make_room(&self, api_keys: &mut Vec<APIKey>, incoming: usize) {
+        let Some(max_keys) = self.max_keys else {
+            return;
+        };
+        while !api_keys.is_empty() && api_keys.len() > max_keys.saturating_sub(incoming) {
+            let mut oldest: Option<(usize, Option<DateTime<Utc>>)> = None;
+            for (index, key) in api_keys.iter().enumerate() {
+                let last_used = key.usage.lock().await.last_used;
+                // An API key that has never been used sorts before every used one.
+                if oldest.is_none_or(|(_, o)| last_used < o) {
+                    oldest = Some((index, last_used));
+                }
+            }
+            let Some((index, _)) = oldest else {
+                return;
+            };
+            let _evicted = self.remove_index(api_keys, index).await;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key = %mask(&_evicted.key), "evicted least recently used API key");
+        }
+    }
+
+    /// Drops the most recently added API keys beyond the pool's cap, which are only there if more
+    /// API keys were added at once than the cap allows.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_keys` - the write-locked API keys of the pool.
+    fn trim(&self, api_keys: &mut Vec<APIKey>) {
+        if let Some(max_keys) = self.max_keys {
+            api_keys.truncate(max_keys);
+        }
+    }
+
+    /// Removes every API key that has been handed out as many times as its lifetime allows, and
+    /// returns the removed API keys.
+    ///
+    /// Only API keys created with [`APIKey::with_max_total_uses`] can be exhausted.
+    pub async fn reap_exhausted(&self) -> Vec<APIKey> {
+        let mut api_keys = self.api_keys.write().await;
+        let mut reaped = Vec::new();
+        let mut index = 0;
+        while index < api_keys.len() {
+            let exhausted = {
+                let key = &api_keys[index];
+                key.is_exhausted(&*key.usage.lock().await)
+            };
+            if exhausted {
+                reaped.push(self.remove_index(&mut api_keys, index).await);
+            } else {
+                index += 1;
+            }
+        }
+        #[cfg(feature = "tracing")]
+        if !reaped.is_empty() {
+            tracing::debug!(count = reaped.len(), "reaped exhausted API keys");
+        }
+        reaped
+    }
+
+    /// Enables or disables an API key, and returns whether a key was found.
+    ///
+    /// A disabled API key stays in the pool but is never handed out, even if its rate limit allows
+    /// it. If multiple API keys share the same code, all of them are updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key to be updated.
+    /// * `enabled` - whether the API key can be handed out.
+    pub async fn set_enabled(&self, key: &str, enabled: bool) -> bool {
+        let mut api_keys = self.api_keys.write().await;
+        let mut found = false;
+        for k in api_keys.iter_mut().filter(|k| k.key == key) {
+            k.enabled = enabled;
+            found = true;
+        }
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+        found
+    }
+
+    /// Forgets the recorded uses of an API key, so that its rate limit allows it to be used to the
+    /// full again, and returns whether a key was found.
+    ///
+    /// Token buckets are refilled. The lifetime count of uses and any cooldown following a
+    /// reported failure are kept. If multiple API keys share the same code, all of them are reset.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key to be reset.
+    pub async fn reset_key(&self, key: &str) -> bool {
+        let api_keys = self.api_keys.read().await;
+        let mut found = false;
+        for k in api_keys.iter().filter(|k| k.key == key) {
+            let mut usage = k.usage.lock().await;
+            usage.windows = k.policies.iter().map(Window::new).collect();
+            found = true;
+        }
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+        found
+    }
+
+    /// Reports that a request made with an API key failed, parking the key for a cooldown.
+    ///
+    /// A cooling-down API key is not handed out until the cooldown elapses, even if its rate limit
+    /// allows it. The cooldown doubles with each consecutive failure of the API key, up to
+    /// 1024 times the given cooldown, and starts over once a success is reported with
+    /// [`APIKeyPool::report_success`]. The failure counts against the API key's
+    /// [`APIKeyPool::reliability`]. If no API key matches, this does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key that failed.
+    /// * `cooldown` - how long the API key should be parked for after its first consecutive
+    ///   failure.
+    pub async fn report_failure(&self, key: &str, cooldown: chrono::Duration) {
+        let now = self.clock.now();
+        let mut api_keys = self.api_keys.write().await;
+        for k in api_keys.iter_mut().filter(|k| k.key == key) {
+            let mut usage = k.usage.lock().await;
+            let doublings = usage.consecutive_failures.min(MAX_COOLDOWN_DOUBLINGS);
+            let cooldown = cooldown
+                .checked_mul(1 << doublings)
+                .unwrap_or(chrono::TimeDelta::MAX);
+            let until = now
+                .checked_add_signed(cooldown)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+            usage.failures += 1;
+            usage.consecutive_failures = usage.consecutive_failures.saturating_add(1);
+            drop(usage);
+            k.cooldown_until = Some(until);
+        }
+    }
+
+    /// Reports that a request made with an API key succeeded, which counts towards the API key's
+    /// [`APIKeyPool::reliability`] and starts its failure cooldowns over.
+    ///
+    /// If no API key matches, this does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key that succeeded.
+    pub async fn report_success(&self, key: &str) {
+        let api_keys = self.api_keys.read().await;
+        for k in api_keys.iter().filter(|k| k.key == key) {
+            let mut usage = k.usage.lock().await;
+            usage.successes += 1;
+            usage.consecutive_failures = 0;
+        }
+    }
+
+    /// Returns the share of reported requests made with an API key that succeeded, or `None` if
+    /// no API key matches or no outcome has been reported for it.
+    ///
+    /// Outcomes are reported with [`APIKeyPool::report_success`] and
+    /// [`APIKeyPool::report_failure`]. If multiple API keys share the same code, the first one is
+    /// used.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key.
+    pub async fn reliability(&self, key: &str) -> Option<f64> {
+        let api_keys = self.api_keys.read().await;
+        let api_key = api_keys.iter().find(|k| k.key == key)?;
+        let usage = api_key.usage.lock().await;
+        let outcomes = usage.successes + usage.failures;
+        (outcomes > 0).then(|| usage.successes as f64 / outcomes as f64)
+    }
+
+    /// Probes every API key in the pool, parks the ones that fail for a cooldown, and returns the
+    /// outcome for each API key.
+    ///
+    /// This lets operators run periodic validation sweeps, for example by making a cheap request
+    /// to the upstream with each API key. The probes run concurrently, at most eight at a time,
+    /// and the pool is not locked while they run. A probe that panics counts as failed. Failed API
+    /// keys are parked as in [`APIKeyPool::report_failure`].
+    ///
+    /// # Arguments
+    ///
+    /// * `cooldown` - how long an API key that fails its probe should be parked for.
+    /// * `probe` - the probe, which returns whether the API key is healthy.
+    pub async fn probe_all<F, Fut>(
+        &self,
+        cooldown: chrono::Duration,
+        probe: F,
+    ) -> Vec<(String, bool)>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        let keys = self.list_keys().await;
+        let mut healthy = vec![false; keys.len()];
+        let mut probes = JoinSet::new();
+        for (index, key) in keys.iter().enumerate() {
+            if probes.len() >= PROBE_CONCURRENCY {
+                if let Some(Ok((index, ok))) = probes.join_next().await {
+                    healthy[index] = ok;
+                }
+            }
+            let probe = probe(key.clone());
+            probes.spawn(async move { (index, probe.await) });
+        }
+        while let Some(result) = probes.join_next().await {
+            if let Ok((index, ok)) = result {
+                healthy[index] = ok;
+            }
+        }
+        for (key, _) in keys.iter().zip(&healthy).filter(|(_, &ok)| !ok) {
+            self.report_failure(key, cooldown).await;
+        }
+        keys.into_iter().zip(healthy).collect()
+    }
+
+    /// Updates the rate limit policy of an API key, keeping its usage history, and returns whether a
+    /// key was found.
+    ///
+    /// The usage history is migrated so that the same share of the rate limit window stays used,
+    /// which neither permits a burst nor blocks the API key for longer than the old policy would
+    /// have, relative to the window:
+    ///
+    /// * If the window length changes, the age of every recorded use is scaled by the ratio of
+    ///   the new window to the old one. Narrowing a window from 60 to 10 seconds turns a use made
+    ///   30 seconds ago into one made 5 seconds ago, rather than forgetting it.
+    /// * If the number of uses allowed changes, the number of recorded uses is scaled by the
+    ///   ratio of the new count to the old one, rounding up, by dropping the oldest uses. Going
+    ///   from 100 to 60 uses with 80 recorded keeps the 48 most recent uses.
+    /// * A token bucket keeps the same share of its burst, and a policy changed into a token
+    ///   bucket starts with the share of the window that was left.
+    ///
+    /// Only the primary policy is replaced: an API key governed by several policies keeps the
+    /// others, along with their usage history. Use [`APIKeyPool::update_policies`] to replace all
+    /// of them. The room kept for recorded uses is resized to the new count, so tightening a
+    /// policy releases memory. If multiple API keys share the same code, all of them are updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key to be updated.
+    /// * `policy` - the new primary rate limit policy governing the API key.
+    pub async fn update_policy(&self, key: &str, policy: RateLimitPolicy) -> bool {
+        let now = self.clock.now();
+        let mut api_keys = self.api_keys.write().await;
+        let mut found = false;
+        for k in api_keys.iter_mut().filter(|k| k.key == key) {
+            let mut policies = k.policies.clone();
+            policies[0] = policy;
+            k.set_policies(policies, now).await;
+            found = true;
+        }
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+        found
+    }
+
+    /// Replaces every rate limit policy of an API key, keeping its usage history, and returns
+    /// whether a key was found.
+    ///
+    /// Each new policy takes over the usage history of the old policy in the same position,
+    /// migrated as in [`APIKeyPool::update_policy`]. A policy with no counterpart starts with an
+    /// empty history, and the history of an old policy with no counterpart is dropped. If multiple
+    /// API keys share the same code, all of them are updated.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key to be updated.
+    /// * `policies` - the new rate limit policies governing the API key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `policies` is empty.
+    pub async fn update_policies(&self, key: &str, policies: Vec<RateLimitPolicy>) -> bool {
+        assert!(
+            !policies.is_empty(),
+            "an API key needs at least one rate limit policy"
+        );
+        let now = self.clock.now();
+        let mut api_keys = self.api_keys.write().await;
+        let mut found = false;
+        for k in api_keys.iter_mut().filter(|k| k.key == key) {
+            k.set_policies(policies.clone(), now).await;
+            found = true;
+        }
+        self.selector.lock().await.index.invalidate();
+        self.freed.notify_waiters();
+        found
+    }
+
+    /// Releases the memory each API key keeps for uses it has not recorded yet.
+    ///
+    /// Each API key keeps room for as many recorded uses as its policies allow, so that recording
+    /// a use never allocates. For pools of thousands of mostly idle API keys with large counts,
+    /// this room can add up. Shrinking trades it for allocations as the API keys are used again,
+    /// until each has regrown the room it needs. Uses that have aged out of their windows are
+    /// forgotten first.
+    pub async fn shrink_to_fit(&self) {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        for key in api_keys.iter() {
+            let mut usage = key.usage.lock().await;
+            key.prune(&mut usage, now);
+            for window in &mut usage.windows {
+                window.times.shrink_to_fit();
+            }
+        }
+    }
+
+    /// Removes every API key from an API key pool.
+    pub async fn clear(&self) {
+        let mut api_keys = self.api_keys.write().await;
+        api_keys.clear();
+        let mut selector = self.selector.lock().await;
+        selector.next_index = 0;
+        selector.index.invalidate();
+    }
+
+    /// Removes every API key from an API key pool, and returns them in pool order.
+    ///
+    /// Each API key keeps its policy and usage history, so that it can be persisted with
+    /// [`APIKey::recent_uses`] and restored with [`APIKey::with_history`].
+    pub async fn drain(&self) -> Vec<APIKey> {
+        let mut api_keys = self.api_keys.write().await;
+        let drained = std::mem::take(&mut *api_keys);
+        let mut selector = self.selector.lock().await;
+        selector.next_index = 0;
+        selector.index.invalidate();
+        drained
+    }
+
+    /// Returns the number of API keys in the pool.
+    pub async fn len(&self) -> usize {
+        self.api_keys.read().await.len()
+    }
+
+    /// Returns whether the pool has no API keys.
+    pub async fn is_empty(&self) -> bool {
+        self.api_keys.read().await.is_empty()
+    }
+
+    /// Checks to see if an API key with the given code is in the pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key.
+    pub async fn contains(&self, key: &str) -> bool {
+        self.api_keys.read().await.iter().any(|k| k.key == key)
+    }
+
+    /// Returns the code of every API key in the pool, in pool order.
+    pub async fn list_keys(&self) -> Vec<String> {
+        self.api_keys
+            .read()
+            .await
+            .iter()
+            .map(APIKey::get_key)
+            .collect()
+    }
+
+    /// Returns the masked code of every API key in the pool, in pool order.
+    pub async fn list_keys_masked(&self) -> Vec<String> {
+        self.api_keys
+            .read()
+            .await
+            .iter()
+            .map(|k| mask(&k.key))
+            .collect()
+    }
+
+    /// Returns each API key's code along with the number of times it has been handed out, in pool
+    /// order.
+    pub async fn usage_stats(&self) -> Vec<(String, u64)> {
+        let api_keys = self.api_keys.read().await;
+        let mut stats = Vec::with_capacity(api_keys.len());
+        for key in api_keys.iter() {
+            stats.push((key.get_key(), key.usage.lock().await.total_uses));
+        }
+        stats
+    }
+
+    /// Calls the given function with each API key's code, used slots, and count, in pool order,
+    /// without copying any of the codes.
+    ///
+    /// The slots and count describe each API key's primary policy, as in [`KeyInfo`]. The usage
+    /// history of each API key is pruned first, so that uses that have aged out of the window do
+    /// not count. The pool stays locked while the function runs, so it must not call back into the
+    /// pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - the function called with each API key's code, used slots, and count.
+    pub async fn for_each_key<F: FnMut(&str, usize, usize)>(&self, mut f: F) {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        for key in api_keys.iter() {
+            let mut usage = key.usage.lock().await;
+            key.prune(&mut usage, now);
+            let count = usage.ramped_count.unwrap_or(key.policies[0].count);
+            f(&key.key, usage.windows[0].times.len(), count);
+        }
+    }
+
+    /// Returns the codes of the API keys that have not been handed out within the threshold, in
+    /// pool order.
+    ///
+    /// API keys that have never been handed out are included. This helps find API keys that a
+    /// provider may deactivate for going unused.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - how long an API key can go unused before it counts as idle.
+    pub async fn idle_keys(&self, threshold: chrono::Duration) -> Vec<String> {
+        let cutoff = self.clock.now() - threshold;
+        let api_keys = self.api_keys.read().await;
+        let mut idle = Vec::new();
+        for key in api_keys.iter() {
+            let last_used = key.usage.lock().await.last_used;
+            if last_used.is_none_or(|last_used| last_used < cutoff) {
+                idle.push(key.get_key());
+            }
+        }
+        #[cfg(feature = "tracing")]
+        if !idle.is_empty() {
+            tracing::warn!(count = idle.len(), "API keys have been idle");
+        }
+        idle
+    }
+
+    /// Returns the primary rate limit policy of an API key, or `None` if no key matches.
+    ///
+    /// Unlike [`APIKeyPool::key_info`], this does not lock the API key's usage history, so it never
+    /// waits on an acquire in progress. If multiple API keys share the same code, the first one is
+    /// used.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key.
+    pub async fn policy(&self, key: &str) -> Option<RateLimitPolicy> {
+        let api_keys = self.api_keys.read().await;
+        Some(api_keys.iter().find(|k| k.key == key)?.policy())
+    }
+
+    /// Returns a snapshot of the live state of an API key, or `None` if no key matches.
+    ///
+    /// If multiple API keys share the same code, the first one is described.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key.
+    pub async fn key_info(&self, key: &str) -> Option<KeyInfo> {
+        let api_keys = self.api_keys.read().await;
+        let api_key = api_keys.iter().find(|k| k.key == key)?;
+        Some(api_key.info(self.clock.now()).await)
+    }
+
+    /// Returns an owned snapshot of the live state of every API key in the pool.
+    ///
+    /// Every API key is described at the same moment.
+    pub async fn snapshot(&self) -> PoolSnapshot {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut keys = Vec::with_capacity(api_keys.len());
+        for key in api_keys.iter() {
+            keys.push(key.info(now).await);
+        }
+        PoolSnapshot { keys }
+    }
+
+    /// Returns how many more times API keys in the pool can be handed out right now.
+    pub async fn available_capacity(&self) -> usize {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut capacity = 0;
+        for key in api_keys.iter() {
+            capacity += key.remaining(now).await;
+        }
+        let capacity = match &self.selector.lock().await.global {
+            Some(global) => capacity.min(global.remaining(now).await),
+            None => capacity,
+        };
+        capacity
+    }
+
+    /// Returns when an API key in the pool is next available for use, or `None` if the pool is
+    /// empty.
+    ///
+    /// Returns the current time if an API key is available now. The pool-wide budget set with
+    /// [`APIKeyPoolBuilder::with_global_policy`] is taken into account. If no API key can be handed
+    /// out again, because each is disabled or has been handed out as many times as its lifetime
+    /// allows, this is when the soonest of them is next allowed a use by its rate limits and
+    /// cooldown.
+    pub async fn next_available_at(&self) -> Option<DateTime<Utc>> {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut soonest = None;
+        for key in api_keys.iter() {
+            if let Some(at) = key.next_available(now).await {
+                soonest = Some(soonest.map_or(at, |soonest: DateTime<Utc>| soonest.min(at)));
+            }
+        }
+        if soonest.is_none() {
+            for key in api_keys.iter() {
+                let at = key.next_allowed(now).await;
+                soonest = Some(soonest.map_or(at, |soonest: DateTime<Utc>| soonest.min(at)));
+            }
+        }
+        let soonest = soonest?;
+        Some(match &self.selector.lock().await.global {
+            Some(global) => global
+                .next_available(now)
+                .await
+                .map_or(soonest, |at| soonest.max(at)),
+            None => soonest,
+        })
+    }
+
+    /// Returns the masked code of the API key that is next available for use, along with how long
+    /// until then, or `None` if the pool is empty or every API key is disabled.
+    ///
+    /// The wait is zero if an API key is available now. The pool-wide budget set with
+    /// [`APIKeyPoolBuilder::with_global_policy`] is taken into account, as in
+    /// [`APIKeyPool::next_available_at`].
+    pub async fn next_ready(&self) -> Option<(String, chrono::Duration)> {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut soonest: Option<(&APIKey, DateTime<Utc>)> = None;
+        for key in api_keys.iter() {
+            if let Some(at) = key.next_available(now).await {
+                if soonest.is_none_or(|(_, soonest)| at < soonest) {
+                    soonest = Some((key, at));
+                }
+            }
+        }
+        let (key, mut at) = soonest?;
+        if let Some(global) = &self.selector.lock().await.global {
+            if let Some(global_at) = global.next_available(now).await {
+                at = at.max(global_at);
+            }
+        }
+        Some((mask(&key.key), (at - now).max(chrono::Duration::zero())))
+    }
+
+    /// Returns a one-line summary of the pool for logging, such as
+    /// `APIKeyPool(3 keys, 7/12 slots free)`.
+    ///
+    /// The free slots are how many more times the API keys can be used right now, out of the
+    /// capacity of the enabled API keys. The usage history is pruned first, so that uses that have
+    /// aged out of their window count as free.
+    pub async fn summary(&self) -> String {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut free = 0;
+        let mut slots = 0.0;
+        for key in api_keys.iter() {
+            free += key.remaining(now).await;
+            if key.enabled {
+                slots += key.load(now).await.1;
+            }
+        }
+        let noun = if api_keys.len() == 1 { "key" } else { "keys" };
+        format!(
+            "APIKeyPool({} {}, {}/{} slots free)",
+            api_keys.len(),
+            noun,
+            free,
+            slots as usize
+        )
+    }
+
+    /// Returns the fraction of the pool's capacity that is currently used, from `0.0` when every
+    /// API key is idle to `1.0` when every API key is used up.
+    ///
+    /// Disabled API keys are left out. Returns `0.0` if no API key is enabled.
+    pub async fn saturation(&self) -> f64 {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut used = 0.0;
+        let mut capacity = 0.0;
+        for key in api_keys.iter().filter(|k| k.enabled) {
+            let (key_used, key_capacity) = key.load(now).await;
+            used += key_used;
+            capacity += key_capacity;
+        }
+        if capacity > 0.0 {
+            (used / capacity).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Registers a callback that is called with the code of every API key handed out, along with
+    /// the id of the request it was handed out for, if any.
+    ///
+    /// The request id is the one passed to [`APIKeyPool::acquire_tagged`], and is `None` for every
+    /// other acquire. Registering a callback replaces any previously registered one. The callback
+    /// is called after the pool's locks are released.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - the callback, called with the API key code and the request id.
+    pub fn on_acquire(&self, f: impl Fn(&str, Option<&str>) + Send + Sync + 'static) {
+        *self
+            .acquire_callback
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(f));
+    }
+
+    /// Calls the registered acquire callback, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key handed out.
+    /// * `request_id` - the id of the request the API key was handed out for, if any.
+    fn fire_on_acquire(&self, key: &str, request_id: Option<&str>) {
+        let callback = self
+            .acquire_callback
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if let Some(callback) = callback {
+            callback(key, request_id);
+        }
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key if available.
+    pub async fn poll_for_key(&self) -> Option<String> {
+        self.try_acquire().await.ok()
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key if available,
+    /// passing the given request id to the acquire callback.
+    ///
+    /// This lets a callback registered with [`APIKeyPool::on_acquire`] correlate each API key
+    /// handed out with the request it was handed out for, such as for distributed tracing. The
+    /// API key is chosen as in [`APIKeyPool::poll_for_key`].
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - the id of the request the API key is for.
+    pub async fn acquire_tagged(&self, request_id: &str) -> Option<String> {
+        let request = AcquireRequest {
+            request_id: Some(request_id),
+            ..AcquireRequest::default()
+        };
+        Some(self.acquire(request).await.ok()?.key)
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key if available.
+    ///
+    /// Unlike [`APIKeyPool::poll_for_key`], this distinguishes between an empty pool and a pool
+    /// whose API keys are all busy, in which case the error carries how long until the soonest API
+    /// key is available.
+    pub async fn try_acquire(&self) -> Result<String, PoolError> {
+        Ok(self.acquire(AcquireRequest::default()).await?.key)
+    }
+
+    /// Checks the API key pool for any available API keys, and returns a guard holding the API key
+    /// if available.
+    ///
+    /// The API key counts as in flight until the guard is dropped, which lets callers track how
+    /// many requests are outstanding on each API key separately from its rate limit window. An API
+    /// key created with [`APIKey::with_max_concurrent`] is not handed out while it has as many
+    /// guards held as it allows, and dropping a guard wakes waiting acquires.
+    pub async fn acquire_guard(&self) -> Option<KeyGuard> {
+        let request = AcquireRequest {
+            hold: true,
+            ..AcquireRequest::default()
+        };
+        let acquired = self.acquire(request).await.ok()?;
+        Some(KeyGuard::new(
+            acquired.key,
+            acquired.in_flight,
+            acquired.freed,
+        ))
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key along with its
+    /// position in the pool if available.
+    ///
+    /// The position is only valid until API keys are removed from the pool, which shifts the
+    /// API keys after them. An API key handed out by the fallback pool is reported with its
+    /// position in the fallback pool.
+    pub async fn acquire_indexed(&self) -> Option<(usize, String)> {
+        let acquired = self.acquire(AcquireRequest::default()).await.ok()?;
+        Some((acquired.index, acquired.key))
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key along with how
+    /// many more times it can be used right now if available.
+    ///
+    /// The count is taken after this use, so that a fresh API key allowing three uses reports two.
+    /// It accounts for the pool-wide budget, and lets callers batch requests on the same API key
+    /// without checking the pool again.
+    pub async fn acquire_with_remaining(&self) -> Option<(String, usize)> {
+        let acquired = self.acquire(AcquireRequest::default()).await.ok()?;
+        Some((acquired.key, acquired.remaining))
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key along with its
+    /// metadata if available.
+    ///
+    /// The metadata is `None` for an API key created without any, as with [`APIKey::new`].
+    pub async fn acquire_with_meta(&self) -> Option<(String, Option<serde_json::Value>)> {
+        let acquired = self.acquire(AcquireRequest::default()).await.ok()?;
+        Some((acquired.key, acquired.meta.as_deref().cloned()))
+    }
+
+    /// Checks the API key pool for any available API keys, and returns the API key if available
+    /// along with how congested the pool is.
+    ///
+    /// The congestion is the pool's [`APIKeyPool::saturation`] at the moment of the call, before
+    /// the API key is handed out, so that callers can shed load as it nears `1.0`. The API key is
+    /// chosen as in [`APIKeyPool::poll_for_key`].
+    pub async fn acquire_with_pressure(&self) -> (Option<String>, f64) {
+        let pressure = self.saturation().await;
+        (self.poll_for_key().await, pressure)
+    }
+
+    /// Reserves a use of an API key at the given time, and returns the API key if one will be
+    /// available then.
+    ///
+    /// The reservation is recorded as a use at that time right away, so that no other caller can
+    /// book the same slot. Under a sliding window, it counts against the API key's used slots from
+    /// now on. Under a fixed window, it only counts against the window it falls in, so reserving a
+    /// slot in the next window leaves the current one untouched. A time in the past is taken as
+    /// the current time. API keys are considered in pool order, starting after the last one handed
+    /// out, and the pool-wide budget must also allow a use at that time. Returns `None` if the
+    /// pool is shut down. This calls the acquire callback once the pool's locks are released.
+    ///
+    /// # Arguments
+    ///
+    /// * `when` - when the API key is to be used.
+    pub async fn reserve_at(&self, when: DateTime<Utc>) -> Option<String> {
+        if self.is_shut_down() {
+            return None;
+        }
+        let now = self.clock.now();
+        let when = when.max(now);
+        let key = {
+            let api_keys = self.api_keys.read().await;
+            let mut selector = self.selector.lock().await;
+            if let Some(global) = &selector.global {
+                let mut usage = global.usage.lock().await;
+                global.prune(&mut usage, now);
+                if !global.is_free_at(&usage, when) {
+                    return None;
+                }
+            }
+            let len = api_keys.len();
+            let mut reserved = None;
+            for offset in 0..len {
+                let index = (selector.next_index + offset) % len;
+                let api_key = &api_keys[index];
+                if !selector.is_eligible(api_key, None) {
+                    continue;
+                }
+                let mut usage = api_key.usage.lock().await;
+                api_key.prune(&mut usage, now);
+                if api_key.is_free_at(&usage, when) {
+                    api_key.record(&mut usage, when, now);
+                    reserved = Some((index, api_key.get_key()));
+                    break;
+                }
+            }
+            let (index, key) = reserved?;
+            if let Some(global) = &selector.global {
+                let mut usage = global.usage.lock().await;
+                global.record(&mut usage, when, now);
+            }
+            let at = api_keys[index].next_available_bound(now).await;
+            selector.index.update(index, at);
+            selector.next_index = (index + 1) % len;
+            #[cfg(feature = "metrics")]
+            metrics::counter!("api_key_pool_acquires_total").increment(1);
+            key
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(key = %mask(&key), %when, "reserved API key");
+        self.fire_on_acquire(&key, None);
+        Some(key)
+    }
+
+    /// Checks the API key pool for an available API key whose code is one of the given codes, and
+    /// returns the API key if available.
+    ///
+    /// This suits a request that is only valid against certain provider accounts. Other API keys
+    /// are never returned, even if none of the allowed ones is available.
+    ///
+    /// # Arguments
+    ///
+    /// * `allowed` - the codes of the API keys that may be returned.
+    pub async fn acquire_any_of(&self, allowed: &[&str]) -> Option<String> {
+        let request = AcquireRequest {
+            allowed: Some(allowed),
+            ..AcquireRequest::default()
+        };
+        Some(self.acquire(request).await.ok()?.key)
+    }
+
+    /// Checks the API key pool for an API key that can be used at least the given number of times
+    /// right now, and returns the API key if available.
+    ///
+    /// This suits a job that must make several requests without interruption. API keys with fewer
+    /// uses left are skipped, and if no single API key has enough, `None` is returned. Only one use
+    /// is taken from the API key, and the pool-wide budget is not checked for the extra uses.
+    ///
+    /// # Arguments
+    ///
+    /// * `min` - the number of times the API key must be usable.
+    pub async fn acquire_with_min_capacity(&self, min: usize) -> Option<String> {
+        let request = AcquireRequest {
+            min_capacity: min,
+            ..AcquireRequest::default()
+        };
+        Some(self.acquire(request).await.ok()?.key)
+    }
+
+    /// Checks the API key pool for an available API key for a session, and returns the API key if
+    /// available.
+    ///
+    /// Each session id maps to the same API key on every call, so that a session keeps hitting one
+    /// API key while it is available. If that API key is busy, another available API key is chosen
+    /// as in [`APIKeyPool::poll_for_key`]. Adding or removing API keys may change the mapping.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - the identifier of the session.
+    pub async fn acquire_for(&self, session_id: &str) -> Option<String> {
+        let request = AcquireRequest {
+            session_id: Some(session_id),
+            ..AcquireRequest::default()
+        };
+        Some(self.acquire(request).await.ok()?.key)
+    }
+
+    /// Checks the API key pool for an available API key with the given tag, and returns the API
+    /// key if available.
+    ///
+    /// API keys without a tag are never returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - the tag of the group, e.g. the name of the upstream service.
+    pub async fn acquire_from_group(&self, tag: &str) -> Option<String> {
+        let request = AcquireRequest {
+            tag: Some(tag),
+            ..AcquireRequest::default()
+        };
+        Some(self.acquire(request).await.ok()?.key)
+    }
+
+    /// Returns how many guards are currently held for an API key, or `None` if no key matches.
+    ///
+    /// If multiple API keys share the same code, their counts are summed.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key.
+    pub async fn in_flight(&self, key: &str) -> Option<usize> {
+        let api_keys = self.api_keys.read().await;
+        let mut matching = api_keys.iter().filter(|k| k.key == key).peekable();
+        matching.peek()?;
+        Some(matching.map(|k| k.in_flight.load(Ordering::SeqCst)).sum())
+    }
+
+    /// Checks the API key pool for `n` available uses, and uses and returns them if available.
+    ///
+    /// Either all `n` uses are taken, or none are. Uses are taken from the API keys in round robin
+    /// order, so an API key with room for several uses may be returned several times. Asking for
+    /// no uses returns an empty list without looking at the API keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - the number of uses to take.
+    pub async fn try_acquire_n(&self, n: usize) -> Option<Vec<String>> {
+        if self.is_shut_down() {
+            return None;
+        }
+        if n == 0 {
+            return Some(Vec::new());
+        }
+        let keys = {
+            let now = self.clock.now();
+            let api_keys = self.api_keys.read().await;
+            let mut selector = self.selector.lock().await;
+            if let Some(global) = &selector.global {
+                if global.remaining(now).await < n {
+                    return None;
+                }
+            }
+            // Plan how many uses to take from each API key before taking any.
+            let len = api_keys.len();
+            let mut plan = Vec::new();
+            let mut planned = 0;
+            for offset in 0..len {
+                if planned == n {
+                    break;
+                }
+                let index = (selector.next_index + offset) % len;
+                let key = &api_keys[index];
+                if !selector.is_eligible(key, None)
+                    || (matches!(
+                        self.strategy,
+                        SelectionStrategy::Weighted | SelectionStrategy::SmoothWeighted
+                    ) && key.weight == 0)
+                {
+                    continue;
+                }
+                let uses = key.remaining(now).await.min(n - planned);
+                if uses > 0 {
+                    plan.push((index, uses));
+                    planned += uses;
+                }
+            }
+            if planned < n {
+                return None;
+            }
+            let mut keys = Vec::with_capacity(n);
+            for (index, uses) in plan {
+                for _ in 0..uses {
+                    keys.extend(api_keys[index].try_acquire(now, false).await);
+                }
+                let at = api_keys[index].next_available_bound(now).await;
+                selector.index.update(index, at);
+                selector.next_index = (index + 1) % len;
+            }
+            // The locks held since planning keep every other acquire out, so each planned use,
+            // like each use of the pool-wide budget checked above, is still there to take.
+            debug_assert_eq!(keys.len(), n, "planned API key uses were taken");
+            if let Some(global) = &selector.global {
+                for _ in 0..n {
+                    let taken = global.try_acquire(now, false).await;
+                    debug_assert!(taken.is_some(), "pool-wide use was taken");
+                }
+            }
+            #[cfg(feature = "metrics")]
+            metrics::counter!("api_key_pool_acquires_total").increment(n as u64);
+            keys
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(count = n, "acquired API key uses");
+        for key in &keys {
+            self.fire_on_acquire(key, None);
+        }
+        Some(keys)
+    }
+
+    /// Returns the API key that would be handed out next, without using it.
+    ///
+    /// This runs the same selection as [`APIKeyPool::poll_for_key`], but records no use, so the
+    /// API key's rate limit window and usage stats are left untouched. Returns `None` if no API key
+    /// is available.
+    pub async fn peek_key(&self) -> Option<String> {
+        if let Some(key) = self.peek_available().await {
+            return Some(key);
+        }
+        match &self.fallback {
+            Some(fallback) => Box::pin(fallback.peek_key()).await,
+            None => None,
+        }
+    }
+
+    /// Returns the API keys that a run of acquires would hand out right now, without using any.
+    ///
+    /// The acquires run the pool's selection against a copy of its usage history, so each
+    /// simulated acquire sees the uses of the ones before it, but the pool's rate limit windows
+    /// and usage stats are left untouched. Every simulated acquire happens at the current time,
+    /// and the fallback pool is not consulted. An entry is `None` where that acquire would find no
+    /// API key available.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - the number of acquires to simulate.
+    pub async fn simulate_acquires(&self, n: usize) -> Vec<Option<String>> {
+        let pool = {
+            let api_keys = self.api_keys.read().await;
+            let selector = self.selector.lock().await;
+            let mut detached = Vec::with_capacity(api_keys.len());
+            for api_key in api_keys.iter() {
+                detached.push(api_key.detach().await);
+            }
+            APIKeyPool {
+                api_keys: Arc::new(RwLock::new(detached)),
+                selector: Arc::new(Mutex::new(selector.fork().await)),
+                clock: Arc::clone(&self.clock),
+                ..APIKeyPool::with_strategy(self.strategy)
+            }
+        };
+        let mut keys = Vec::with_capacity(n);
+        for _ in 0..n {
+            let acquired = pool.take_available(AcquireRequest::default()).await;
+            keys.push(acquired.ok().map(|acquired| acquired.key));
+        }
+        keys
+    }
+
+    /// Returns the API key of this pool that would be handed out next, without using it.
+    async fn peek_available(&self) -> Option<String> {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut selector = self.selector.lock().await;
+        if let Some(global) = &selector.global {
+            if !global.is_ready(now).await {
+                return None;
+            }
+        }
+        // Restore the selection state afterwards, so that the next acquire makes the same choice.
+        let rng = selector.rng.clone();
+        let mut current_weights = Vec::new();
+        if self.strategy == SelectionStrategy::SmoothWeighted {
+            for key in api_keys.iter() {
+                current_weights.push(key.usage.lock().await.current_weight);
+            }
+        }
+        let index = selector
+            .choose(self.strategy, &api_keys, AcquireRequest::default(), now)
+            .await;
+        selector.rng = rng;
+        for (key, current_weight) in api_keys.iter().zip(current_weights) {
+            key.usage.lock().await.current_weight = current_weight;
+        }
+        Some(api_keys[index?].get_key())
+    }
+
+    /// Chooses an available API key, uses it, and returns it.
+    ///
+    /// If none of this pool's API keys is available, the fallback pool is consulted. This calls
+    /// the acquire callback once the pool's locks are released.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - what is asked of the API key.
+    async fn acquire(&self, request: AcquireRequest<'_>) -> Result<Acquired, PoolError> {
+        if self.is_shut_down() {
+            return Err(PoolError::ShutDown);
+        }
+        let mut result = self.take_available(request).await;
+        #[cfg(feature = "metrics")]
+        if result.is_ok() {
+            metrics::counter!("api_key_pool_acquires_total").increment(1);
+        }
+        if let (&Err(error), Some(fallback)) = (&result, &self.fallback) {
+            result = Box::pin(fallback.acquire(request))
+                .await
+                .map(|mut acquired| {
+                    // This pool's waiters fell back too, so releasing the API key wakes them.
+                    acquired.freed.push(Arc::clone(&self.freed));
+                    acquired
+                })
+                .map_err(|fallback_error| error.or(fallback_error));
+        }
+        if let Ok(acquired) = &result {
+            self.fire_on_acquire(&acquired.key, request.request_id);
+        }
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(acquired) => tracing::debug!(key = %mask(&acquired.key), "acquired API key"),
+            Err(PoolError::AllBusy { retry_after }) => tracing::debug!(
+                retry_after_ms = retry_after.num_milliseconds(),
+                "all API keys are busy"
+            ),
+            Err(error) => tracing::debug!(%error, "no API key available"),
+        }
+        result
+    }
+
+    /// Chooses an available API key under the pool's locks, uses it, and returns it.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - what is asked of the API key.
+    async fn take_available(&self, request: AcquireRequest<'_>) -> Result<Acquired, PoolError> {
+        let now = self.clock.now();
+        let api_keys = self.api_keys.read().await;
+        let mut selector = self.selector.lock().await;
+        let len = api_keys.len();
+        // The pool-wide budget applies on top of each API key's own limit.
+        let global_wait = match &selector.global {
+            Some(global) => match global.next_available(now).await {
+                Some(at) => at - now,
+                None => chrono::Duration::zero(),
+            },
+            None => chrono::Duration::zero(),
+        };
+        // The index tells whether every API key is busy without checking each of them.
+        let soonest = selector.index.soonest(&api_keys, now).await;
+        if global_wait.is_zero() && soonest.is_some_and(|(_, at)| at <= now) {
+            let preferred = request
+                .session_id
+                .map(|session_id| session_index(session_id, len))
+                .filter(|&index| selector.is_eligible(&api_keys[index], request.tag));
+            let chosen = match preferred {
+                Some(index) if selector.can_choose(&api_keys[index], request, now).await => {
+                    Some(index)
+                }
+                _ => {
+                    selector
+                        .choose(self.strategy, &api_keys, request, now)
+                        .await
+                }
+            };
+            if let Some(index) = chosen {
+                // Only the choice needs the API keys locked. The chosen API key is used through a
+                // handle sharing its usage history, while the selector keeps other acquires out.
+                let api_key = api_keys[index].share();
+                drop(api_keys);
+                if let Some(key) = api_key.try_acquire(now, request.hold).await {
+                    selector.next_index = (index + 1) % len;
+                    let at = api_key.next_available_bound(now).await;
+                    selector.index.update(index, at);
+                    let mut remaining = api_key.remaining(now).await;
+                    if let Some(global) = &selector.global {
+                        global.try_acquire(now, false).await;
+                        remaining = remaining.min(global.remaining(now).await);
+                    }
+                    return Ok(Acquired {
+                        index,
+                        key,
+                        in_flight: api_key.in_flight,
+                        meta: api_key.meta,
+                        freed: vec![Arc::clone(&self.freed)],
+                        remaining,
+                    });
+                }
+            }
+        }
+        if len == 0 {
+            return Err(PoolError::Empty);
+        }
+        match soonest {
+            // Some API key is due but none could be chosen, e.g. because every due API key failed
+            // the health check, so check again shortly rather than right away.
+            Some((_, at)) if global_wait.is_zero() && at <= now => Err(PoolError::AllBusy {
+                retry_after: chrono::Duration::milliseconds(RECHECK_INTERVAL_MS),
+            }),
+            Some((_, at)) => Err(PoolError::AllBusy {
+                retry_after: (at - now).max(global_wait).max(chrono::Duration::zero()),
+            }),
+            None => Err(PoolError::AllDisabled),
+        }
+    }
+
+    /// Registers a health check that an API key must pass to be handed out, even if its rate limit
+    /// allows it.
+    ///
+    /// The check is called with the code of each API key considered while the pool is locked, so
+    /// it must be cheap, such as reading a cached liveness flag. Registering a check replaces any
+    /// previously registered one.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - the health check, which returns whether the API key is healthy.
+    pub async fn set_health_check(&self, f: impl Fn(&str) -> bool + Send + Sync + 'static) {
+        self.selector.lock().await.health_check = Some(Arc::new(f));
+    }
+
+    /// Seeds the pool's random number generator, so that randomized selection, such as
+    /// [`SelectionStrategy::Weighted`] and [`SelectionStrategy::Random`], and jitter are
+    /// reproducible from then on.
+    ///
+    /// Without a seed, the random number generator is seeded from entropy. Clones of the pool share
+    /// the seeded generator. To seed a pool as it is built, use
+    /// [`APIKeyPoolBuilder::with_rng_seed`].
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - the seed of the random number generator.
+    pub async fn set_rng_seed(&self, seed: u64) {
+        self.selector.lock().await.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    /// Sets the maximum random delay added to each sleep in [`APIKeyPool::wait_for_key`].
+    ///
+    /// Without jitter, every task waiting for the same API key wakes at the same instant and all
+    /// but one go back to sleep. A random delay spreads their wakeups out. The default is no
+    /// jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - the maximum delay added to each sleep.
+    pub async fn set_jitter(&self, max: chrono::Duration) {
+        self.selector.lock().await.max_jitter = max.max(chrono::Duration::zero());
+    }
+
+    /// Sets the backoff added to each sleep in [`APIKeyPool::wait_for_key`] while acquires keep
+    /// failing.
+    ///
+    /// Under sustained overload, every waiting task sleeping for exactly as long as the soonest
+    /// API key needs can still contend for it at once. With a backoff, each consecutive failed
+    /// acquire within one wait sleeps longer, and the backoff starts over with the next wait. The
+    /// default is no backoff.
+    ///
+    /// # Arguments
+    ///
+    /// * `backoff` - the backoff added to each sleep.
+    pub async fn set_backoff(&self, backoff: Backoff) {
+        self.selector.lock().await.backoff = Some(backoff);
+    }
+
+    /// Sets how many of the most recent waits for an API key are kept for
+    /// [`APIKeyPool::wait_latency_percentiles`], forgetting the oldest ones beyond it.
+    ///
+    /// The default is 1024 waits.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - the most waits kept.
+    pub async fn set_wait_samples(&self, capacity: usize) {
+        let mut selector = self.selector.lock().await;
+        selector.max_waits = capacity;
+        while selector.waits.len() > capacity {
+            selector.waits.pop_front();
+        }
+    }
+
+    /// Returns the 50th, 90th, and 99th percentiles of how long recent waits for an API key took,
+    /// or zero durations if no wait has finished yet.
+    ///
+    /// Every wait in [`APIKeyPool::wait_for_key`], [`APIKeyPool::acquire_timeout`], and
+    /// [`APIKeyPool::scoped`] that hands out an API key is timed with the pool's clock, and the
+    /// most recent ones are kept, as set with [`APIKeyPool::set_wait_samples`].
+    pub async fn wait_latency_percentiles(
+        &self,
+    ) -> (chrono::Duration, chrono::Duration, chrono::Duration) {
+        let mut waits: Vec<chrono::Duration> =
+            self.selector.lock().await.waits.iter().copied().collect();
+        waits.sort_unstable();
+        // Each percentile is the smallest wait that at least that share of waits took no longer
+        // than.
+        let percentile = |p: usize| match waits.len() {
+            0 => chrono::Duration::zero(),
+            len => waits[(len * p).div_ceil(100).max(1) - 1],
+        };
+        (percentile(50), percentile(90), percentile(99))
+    }
+
+    /// Shuts the pool down, so that it hands out no more API keys.
+    ///
+    /// Every acquire fails from then on, and every pending [`APIKeyPool::wait_for_key`] returns
+    /// `None` promptly rather than waiting for an API key. This lets a long-running service stop
+    /// cleanly. Shutting a pool down shuts down every clone of it, but not its fallback pool.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        #[cfg(feature = "tracing")]
+        tracing::debug!("shut down API key pool");
+    }
+
+    /// Checks to see if the pool has been shut down.
+    pub fn is_shut_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Waits until the pool is shut down.
+    async fn shut_down(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        // The sender lives as long as the pool, so this only returns once the pool is shut down.
+        let _ = shutdown.wait_for(|&shut_down| shut_down).await;
+    }
+
+    /// Waits until an API key is available, and returns it, or returns `None` if the pool is shut
+    /// down first.
+    ///
+    /// Rather than polling on a fixed interval, this combines a timer with a notification. The
+    /// timer sleeps until the soonest API key in the pool becomes available, which is when its
+    /// oldest use leaves the rate limit window, plus any backoff set with
+    /// [`APIKeyPool::set_backoff`] and any jitter set with [`APIKeyPool::set_jitter`]. The
+    /// notification wakes every waiter at once when an API key becomes available for another
+    /// reason, such as being added, enabled, or given a new policy, so waiters need not wait out
+    /// their timers. If the pool is empty or every API key is disabled, this waits for such a
+    /// notification, or until the pool is shut down.
+    ///
+    /// With the `metrics` feature, the time waited is recorded in the `api_key_pool_wait_seconds`
+    /// histogram, and every API key handed out by the pool counts towards the
+    /// `api_key_pool_acquires_total` counter.
+    pub async fn wait_for_key(&self) -> Option<String> {
+        Some(self.wait_for(AcquireRequest::default()).await?.key)
+    }
+
+    /// Waits until an API key is available, and uses it, as in [`APIKeyPool::wait_for_key`].
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - what is asked of the API key.
+    async fn wait_for(&self, request: AcquireRequest<'_>) -> Option<Acquired> {
+        #[cfg(feature = "metrics")]
+        let start = time::Instant::now();
+        let started = self.clock.now();
+        let mut failures = 0;
+        loop {
+            // Register for the notification before trying, so that none is missed in between.
+            let freed = self.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            match self.acquire(request).await {
+                Ok(acquired) => {
+                    #[cfg(feature = "metrics")]
+                    metrics::histogram!("api_key_pool_wait_seconds")
+                        .record(start.elapsed().as_secs_f64());
+                    let waited = self.clock.now() - started;
+                    self.selector.lock().await.record_wait(waited);
+                    return Some(acquired);
+                }
+                Err(PoolError::AllBusy { retry_after }) => {
+                    let mut selector = self.selector.lock().await;
+                    let backoff = selector
+                        .backoff
+                        .map_or(chrono::Duration::zero(), |backoff| backoff.delay(failures));
+                    let sleep = retry_after + backoff + selector.jitter();
+                    drop(selector);
+                    failures = failures.saturating_add(1);
+                    tokio::select! {
+                        _ = time::sleep(sleep.to_std().unwrap_or_default()) => {}
+                        _ = freed => {}
+                        _ = self.shut_down() => return None,
+                    }
+                }
+                Err(PoolError::Empty | PoolError::AllDisabled) => {
+                    tokio::select! {
+                        _ = freed => {}
+                        _ = self.shut_down() => return None,
+                    }
+                }
+                Err(PoolError::ShutDown) => return None,
+            }
+        }
+    }
+
+    /// Waits until an API key is available, calls the given function with it, and returns the
+    /// function's result.
+    ///
+    /// A success is reported as in [`APIKeyPool::report_success`]. If the function reports that
+    /// the API key was rate-limited, the API key is parked for the reported cooldown as in
+    /// [`APIKeyPool::report_failure`], and the function is called again with the next available
+    /// API key, up to 5 times in all. If every call is rate-limited, [`PoolError::AllBusy`] is
+    /// returned with the last reported cooldown, converted into `E`. Any other failure is returned
+    /// as is. If the pool is shut down while waiting for an API key, [`PoolError::ShutDown`] is
+    /// returned, converted into `E`.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - the function making a request with an API key.
+    pub async fn with_key<F, Fut, T, E>(&self, f: F) -> Result<T, E>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T, CallError<E>>>,
+        E: From<PoolError>,
+    {
+        provider::with_key(self, f).await
+    }
+
+    /// Waits until an API key is available, runs the given async work with it, and returns the
+    /// work's result, or returns `None` if the pool is shut down first.
+    ///
+    /// The use of the API key is recorded once, when it is handed out, whether the work succeeds,
+    /// fails, or panics. The API key counts as in flight while the work runs, as with a guard from
+    /// [`APIKeyPool::acquire_guard`], and is released however the work ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - the work to run with the API key.
+    pub async fn scoped<F, Fut, T>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let request = AcquireRequest {
+            hold: true,
+            ..AcquireRequest::default()
+        };
+        let acquired = self.wait_for(request).await?;
+        let _guard = KeyGuard::new(acquired.key.clone(), acquired.in_flight, acquired.freed);
+        Some(f(acquired.key).await)
+    }
+
+    /// Checks the API key pool for an available API key, and if every API key is busy, waits until
+    /// the soonest one is due and checks exactly once more.
+    ///
+    /// This bounds the wait by the rate limit windows of the API keys, unlike
+    /// [`APIKeyPool::wait_for_key`], which keeps trying until it gets an API key. Returns `None`
+    /// if the second check finds no API key either, if the pool is empty or every API key is
+    /// disabled, or if the pool is shut down while waiting.
+    pub async fn acquire_next_window(&self) -> Option<String> {
+        let retry_after = match self.try_acquire().await {
+            Ok(key) => return Some(key),
+            Err(PoolError::AllBusy { retry_after }) => retry_after,
+            Err(_) => return None,
+        };
+        tokio::select! {
+            _ = time::sleep(retry_after.to_std().unwrap_or_default()) => {}
+            _ = self.shut_down() => return None,
+        }
+        self.try_acquire().await.ok()
+    }
+
+    /// Waits until an API key is available, and returns it, or returns `None` if the timeout
+    /// elapses or the pool is shut down first.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - how long to wait for an API key.
+    pub async fn acquire_timeout(&self, timeout: chrono::Duration) -> Option<String> {
+        time::timeout(timeout.to_std().unwrap_or_default(), self.wait_for_key())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Returns a stream that yields an API key whenever one is available.
+    ///
+    /// The stream waits for the soonest API key as in [`APIKeyPool::wait_for_key`], so it never
+    /// busy-spins and yields until the pool is shut down. If the pool is empty, the stream waits
+    /// for API keys to be added, and ends once the pool is shut down.
+    #[cfg(feature = "stream")]
+    pub fn into_key_stream(self) -> impl tokio_stream::Stream<Item = String> {
+        stream::KeyStream::new(self)
+    }
+}
+
+/// Returns the position of the API key that a session maps to.
+///
+/// # Arguments
+///
+/// * `session_id` - the identifier of the session.
+/// * `len` - the number of API keys in the pool, which must not be zero.
+fn session_index(session_id: &str, len: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    session_id.hash(&mut hasher);
+    (hasher.finish() % len as u64) as usize
+}
+
+/// State used to choose among the available API keys of a pool.
+struct Selector {
+    /// Index of the API key to start the next scan from, so that usage is spread across keys.
+    next_index: usize,
+    /// Random number generator used by randomized selection strategies.
+    rng: SmallRng,
+    /// Index of the API keys by when each is next available.
+    index: ReadyIndex,
+    /// The pool-wide budget, tracked like an API key, that every acquire also uses.
+    global: Option<APIKey>,
+    /// The maximum random delay added to each sleep while waiting for an API key.
+    max_jitter: chrono::Duration,
+    /// Check that an API key must pass to be chosen, if any.
+    health_check: Option<HealthCheck>,
+    /// The backoff added to each sleep after consecutive failed acquires, if any.
+    backoff: Option<Backoff>,
+    /// How long recent waits for an API key took, oldest first.
+    waits: VecDeque<chrono::Duration>,
+    /// The most waits kept in `waits`.
+    max_waits: usize,
+}
+
+impl Selector {
+    /// Returns the selection state for an empty pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `global_policy` - the pool-wide rate limit policy, if any.
+    fn new(global_policy: Option<RateLimitPolicy>) -> Self {
+        Self {
+            next_index: 0,
+            rng: rand::make_rng(),
+            index: ReadyIndex::new(),
+            global: global_policy.map(|policy| APIKey::new("", policy)),
+            max_jitter: chrono::Duration::zero(),
+            health_check: None,
+            backoff: None,
+            waits: VecDeque::new(),
+            max_waits: DEFAULT_WAIT_SAMPLES,
+        }
+    }
+
+    /// Returns a copy of the selection state whose pool-wide budget has a usage history of its own,
+    /// and whose index is rebuilt on first use.
+    async fn fork(&self) -> Self {
+        let global = match &self.global {
+            Some(global) => Some(global.detach().await),
+            None => None,
+        };
+        Self {
+            next_index: self.next_index,
+            rng: self.rng.clone(),
+            index: ReadyIndex::new(),
+            global,
+            max_jitter: self.max_jitter,
+            health_check: self.health_check.clone(),
+            backoff: self.backoff,
+            waits: self.waits.clone(),
+            max_waits: self.max_waits,
+        }
+    }
+
+    /// Records how long a wait for an API key took, forgetting the oldest wait if the record is
+    /// full.
+    ///
+    /// # Arguments
+    ///
+    /// * `wait` - how long the wait took.
+    fn record_wait(&mut self, wait: chrono::Duration) {
+        if self.max_waits == 0 {
+            return;
+        }
+        while self.waits.len() >= self.max_waits {
+            self.waits.pop_front();
+        }
+        self.waits.push_back(wait);
+    }
+
+    /// Checks to see if an API key can be chosen, regardless of its rate limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key.
+    /// * `tag` - the tag that the API key must have, if any.
+    fn is_eligible(&self, key: &APIKey, tag: Option<&str>) -> bool {
+        key.in_group(tag)
+            && self
+                .health_check
+                .as_ref()
+                .is_none_or(|check| check(&key.key))
+    }
+
+    /// Checks to see if an API key can be chosen for an acquire right now.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key.
+    /// * `request` - what is asked of the API key.
+    /// * `now` - the current time.
+    async fn can_choose(
+        &self,
+        key: &APIKey,
+        request: AcquireRequest<'_>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if !self.is_eligible(key, request.tag)
+            || request
+                .allowed
+                .is_some_and(|allowed| !allowed.contains(&key.key.as_str()))
+        {
+            return false;
+        }
+        if request.min_capacity > 1 {
+            key.remaining(now).await >= request.min_capacity
+        } else {
+            key.is_ready(now).await
+        }
+    }
+
+    /// Returns a random delay of at most the maximum jitter.
+    fn jitter(&mut self) -> chrono::Duration {
+        if self.max_jitter.is_zero() {
+            return chrono::Duration::zero();
+        }
+        let max = self.max_jitter.num_nanoseconds().unwrap_or(i64::MAX);
+        chrono::Duration::nanoseconds(self.rng.random_range(0..=max))
+    }
+
+    /// Chooses an available API key according to the selection strategy without using it, and
+    /// returns its position in the pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - the selection strategy.
+    /// * `api_keys` - the locked API keys of the pool.
+    /// * `request` - what is asked of the chosen API key.
+    /// * `now` - the current time.
+    async fn choose(
+        &mut self,
+        strategy: SelectionStrategy,
+        api_keys: &[APIKey],
+        request: AcquireRequest<'_>,
+        now: DateTime<Utc>,
+    ) -> Option<usize> {
+        let strategy = match strategy {
+            // The index covers every API key, so when only some are eligible the API key whose most
+            // recent use is the oldest stands in for the one available the longest.
+            SelectionStrategy::EarliestAvailable
+                if request.tag.is_some()
+                    || request.allowed.is_some()
+                    || request.min_capacity > 1
+                    || self.health_check.is_some() =>
+            {
+                SelectionStrategy::LeastRecentlyUsed
+            }
+            _ => strategy,
+        };
+        match strategy {
+            SelectionStrategy::RoundRobin => {
+                let len = api_keys.len();
+                self.index.refresh(api_keys, now).await;
+                // Scan starting after the last used API key, skipping those that the index rules
+                // out so that only API keys which may be due are checked.
+                for offset in 0..len {
+                    let index = (self.next_index + offset) % len;
+                    if !self.index.may_be_ready(index, now) {
+                        continue;
+                    }
+                    let key = &api_keys[index];
+                    if self.can_choose(key, request, now).await {
+                        return Some(index);
+                    }
+                }
+                None
+            }
+            SelectionStrategy::Weighted => {
+                let mut ready = Vec::new();
+                for (index, key) in api_keys.iter().enumerate() {
+                    if key.weight > 0 && self.can_choose(key, request, now).await {
+                        ready.push(index);
+                    }
+                }
+                let total: u64 = ready.iter().map(|&i| u64::from(api_keys[i].weight)).sum();
+                if total == 0 {
+                    return None;
+                }
+                let mut pick = self.rng.random_range(0..total);
+                for index in ready {
+                    let weight = u64::from(api_keys[index].weight);
+                    if pick < weight {
+                        return Some(index);
+                    }
+                    pick -= weight;
+                }
+                None
+            }
+            SelectionStrategy::SmoothWeighted => {
+                let mut best: Option<(usize, i64)> = None;
+                let mut total = 0;
+                // Every available API key gains its weight, and the chosen one gives back the
+                // total, so that over time each is chosen in proportion to its weight.
+                for (index, key) in api_keys.iter().enumerate() {
+                    if key.weight == 0 || !self.can_choose(key, request, now).await {
+                        continue;
+                    }
+                    let weight = i64::from(key.weight);
+                    total += weight;
+                    let mut usage = key.usage.lock().await;
+                    usage.current_weight += weight;
+                    if best.is_none_or(|(_, w)| usage.current_weight > w) {
+                        best = Some((index, usage.current_weight));
+                    }
+                }
+                let (index, _) = best?;
+                api_keys[index].usage.lock().await.current_weight -= total;
+                Some(index)
+            }
+            SelectionStrategy::Random => {
+                let mut ready = Vec::new();
+                for (index, key) in api_keys.iter().enumerate() {
+                    if self.can_choose(key, request, now).await {
+                        ready.push(index);
+                    }
+                }
+                if ready.is_empty() {
+                    return None;
+                }
+                Some(ready[self.rng.random_range(0..ready.len())])
+            }
+            SelectionStrategy::Priority => {
+                let len = api_keys.len();
+                let mut best: Option<(usize, i32)> = None;
+                // Scan starting after the last used API key, so that ties go round-robin.
+                for offset in 0..len {
+                    let index = (self.next_index + offset) % len;
+                    let key = &api_keys[index];
+                    if best.is_some_and(|(_, p)| key.priority <= p) {
+                        continue;
+                    }
+                    if self.can_choose(key, request, now).await {
+                        best = Some((index, key.priority));
+                    }
+                }
+                best.map(|(index, _)| index)
+            }
+            SelectionStrategy::LeastRecentlyUsed => {
+                let mut oldest: Option<(usize, Option<DateTime<Utc>>)> = None;
+                for (index, key) in api_keys.iter().enumerate() {
+                    if !self.can_choose(key, request, now).await {
+                        continue;
+                    }
+                    let last_used = key.usage.lock().await.last_used;
+                    // An API key that has never been used sorts before every used one.
+                    if oldest.is_none_or(|(_, o)| last_used < o) {
+                        oldest = Some((index, last_used));
+                    }
+                }
+                oldest.map(|(index, _)| index)
+            }
+            SelectionStrategy::EarliestAvailable => {
+                let (index, at) = self.index.soonest(api_keys, now).await?;
+                (at <= now).then_some(index)
+            }
+            SelectionStrategy::CapacityProportional => {
+                let mut best: Option<(usize, f64, f64)> = None;
+                for (index, key) in api_keys.iter().enumerate() {
+                    if !self.can_choose(key, request, now).await {
+                        continue;
+                    }
+                    let (used, capacity) = key.load(now).await;
+                    let left = capacity - used;
+                    let share = left / capacity;
+                    if best.is_none_or(|(_, s, l)| share > s || (share == s && left > l)) {
+                        best = Some((index, share, left));
+                    }
+                }
+                best.map(|(index, _, _)| index)
+            }
+        }
+    }
+}
+
+/// A builder for an API key pool, which registers API keys without locking.
+#[derive(Default)]
+pub struct APIKeyPoolBuilder {
+    /// The API keys to be added to the pool.
+    api_keys: Vec<APIKey>,
+    /// The strategy used to choose among available API keys.
+    strategy: SelectionStrategy,
+    /// The pool-wide rate limit policy, if any.
+    global_policy: Option<RateLimitPolicy>,
+    /// The source of the current time, if not the system clock.
+    clock: Option<Arc<dyn Clock>>,
+    /// The seed of the pool's random number generator, if not seeded from entropy.
+    rng_seed: Option<u64>,
+}
+
+impl APIKeyPoolBuilder {
+    /// Returns a builder for an empty API key pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an API key to the pool being built.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key to be added.
+    pub fn with_key(mut self, key: APIKey) -> Self {
+        self.api_keys.push(key);
+        self
+    }
+
+    /// Adds API keys to the pool being built.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - the API keys to be added.
+    pub fn with_keys(mut self, keys: impl IntoIterator<Item = APIKey>) -> Self {
+        self.api_keys.extend(keys);
+        self
+    }
+
+    /// Sets the strategy used to choose among available API keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - the selection strategy.
+    pub fn with_strategy(mut self, strategy: SelectionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets a pool-wide rate limit policy, which applies on top of each API key's own policy.
+    ///
+    /// This models providers that cap usage across all of an account's API keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the pool-wide rate limit policy.
+    pub fn with_global_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.global_policy = Some(policy);
+        self
+    }
+
+    /// Sets the source of the current time, which the pool and all its API keys use for their rate
+    /// limit math.
+    ///
+    /// This lets tests drive readiness with a [`MockClock`] instead of sleeping. Waits such as
+    /// [`APIKeyPool::wait_for_key`] still sleep on the runtime's timer, so a test that waits for API
+    /// keys with `tokio::time::pause` should use a [`TokioClock`], which follows that timer.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - the source of the current time.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Seeds the pool's random number generator, so that randomized selection is reproducible.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - the seed of the random number generator.
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Returns the API key pool.
+    pub fn build(self) -> APIKeyPool {
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let mut api_keys = self.api_keys;
+        for key in &mut api_keys {
+            key.clock = Arc::clone(&clock);
+        }
+        let mut selector = Selector::new(self.global_policy);
+        if let Some(seed) = self.rng_seed {
+            selector.rng = SmallRng::seed_from_u64(seed);
+        }
+        APIKeyPool {
+            api_keys: Arc::new(RwLock::new(api_keys)),
+            selector: Arc::new(Mutex::new(selector)),
+            clock,
+            ..APIKeyPool::with_strategy(self.strategy)
+        }
+    }
+}
+
+/// An API key, with its associated RateLimitPolicy
+pub struct APIKey {
+    /// The API key code.
+    key: String,
+    /// The rate limit policies that govern this API key, all of which must allow a use.
+    ///
+    /// The policies are kept apart from the usage history, so reading them never waits on the
+    /// lock that acquires hold while recording a use.
+    policies: Vec<RateLimitPolicy>,
+    /// The relative share of traffic this API key receives under weighted selection.
+    weight: u32,
+    /// The priority of this API key under priority selection, where higher goes first.
+    priority: i32,
+    /// The tag of the group the API key belongs to, if any.
+    tag: Option<String>,
+    /// The metadata attached to the API key, if any.
+    meta: Option<Arc<serde_json::Value>>,
+    /// Whether the API key can be handed out.
+    enabled: bool,
+    /// The number of times the API key can be handed out over its lifetime, if limited.
+    max_total_uses: Option<u64>,
+    /// When the cooldown following a reported failure ends, if any.
+    cooldown_until: Option<DateTime<Utc>>,
+    /// The number of guards currently held for this API key.
+    in_flight: Arc<AtomicUsize>,
+    /// The most guards that can be held for this API key at once, if limited.
+    max_concurrent: Option<usize>,
+    /// The ramp of the primary policy's count while the API key warms up, if any.
+    ramp: Option<Ramp>,
+    /// Usage history used to calculate if the key is available.
+    usage: Arc<Mutex<Usage>>,
+    /// The source of the current time, which is the pool's clock once the API key is added to one.
+    clock: Arc<dyn Clock>,
+}
+
+impl APIKey {
+    /// Returns an API key with the given policy and code.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    pub fn new(key: &str, policy: RateLimitPolicy) -> Self {
+        Self::with_policies(key, vec![policy])
+    }
+
+    /// Returns an API key with the given code, governed by several rate limit policies at once.
+    ///
+    /// This models providers that enforce both a short burst limit and a longer sustained limit,
+    /// such as 10 uses per second and 1000 uses per hour. Each policy keeps its own usage history,
+    /// and the API key is only available when every policy allows another use.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policies` - the rate limit policies governing the API key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `policies` is empty.
+    pub fn with_policies(key: &str, policies: Vec<RateLimitPolicy>) -> Self {
+        assert!(
+            !policies.is_empty(),
+            "an API key needs at least one rate limit policy"
+        );
+        Self {
+            key: String::from(key),
+            weight: 1,
+            priority: 0,
+            tag: None,
+            meta: None,
+            enabled: true,
+            max_total_uses: None,
+            cooldown_until: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_concurrent: None,
+            ramp: None,
+            usage: Arc::new(Mutex::new(Usage::new(&policies))),
+            clock: Arc::new(SystemClock),
+            policies,
+        }
+    }
+
+    /// Returns an API key with the given policy, code, and weight.
+    ///
+    /// Under [`SelectionStrategy::Weighted`] and [`SelectionStrategy::SmoothWeighted`], an API key
+    /// is chosen proportionally to its weight among the available API keys. An API key with a
+    /// weight of zero is never chosen.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `weight` - the relative share of traffic the API key receives.
+    pub fn with_weight(key: &str, policy: RateLimitPolicy, weight: u32) -> Self {
+        Self {
+            weight,
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy, code, and priority.
+    ///
+    /// Under [`SelectionStrategy::Priority`], an available API key is always chosen over every
+    /// available API key of a lower priority, so that preferred API keys take traffic first, and
+    /// others only take the overflow. API keys default to a priority of zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `priority` - the priority of the API key, where higher goes first.
+    pub fn with_priority(key: &str, policy: RateLimitPolicy, priority: i32) -> Self {
+        Self {
+            priority,
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy, code, and tag.
+    ///
+    /// Tags group API keys, for example by upstream service, so that
+    /// [`APIKeyPool::acquire_from_group`] can hand out an API key from one group only.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `tag` - the tag of the group the API key belongs to.
+    pub fn with_tag(key: &str, policy: RateLimitPolicy, tag: &str) -> Self {
+        Self {
+            tag: Some(String::from(tag)),
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy, code, and metadata.
+    ///
+    /// Metadata such as the provider's base URL or an account label is handed back along with the
+    /// API key by [`APIKeyPool::acquire_with_meta`], so that it need not be kept in a separate map
+    /// keyed by the API key code.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `meta` - the metadata attached to the API key.
+    pub fn with_meta(key: &str, policy: RateLimitPolicy, meta: serde_json::Value) -> Self {
+        Self {
+            meta: Some(Arc::new(meta)),
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy and code, which can only be handed out a limited
+    /// number of times over its lifetime.
+    ///
+    /// This models a hard lifetime quota, such as a free tier capped at 10,000 requests ever. Once
+    /// the API key has been handed out `max_total_uses` times, it is never available again, and
+    /// [`APIKeyPool::reap_exhausted`] removes it from its pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `max_total_uses` - the number of times the API key can be handed out.
+    pub fn with_max_total_uses(key: &str, policy: RateLimitPolicy, max_total_uses: u64) -> Self {
+        Self {
+            max_total_uses: Some(max_total_uses),
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy and code, which can only have a limited number of
+    /// guards held at once.
+    ///
+    /// The API key is only available when both its rate limit allows another use and fewer than
+    /// `max_concurrent` guards from [`APIKeyPool::acquire_guard`] are held for it. API keys handed
+    /// out without a guard do not count.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `max_concurrent` - the most guards that can be held for the API key at once.
+    pub fn with_max_concurrent(key: &str, policy: RateLimitPolicy, max_concurrent: usize) -> Self {
+        Self {
+            max_concurrent: Some(max_concurrent),
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy and code, whose allowed rate ramps up while it
+    /// warms up with the provider.
+    ///
+    /// The policy's count is the target. The effective count starts at `start_count`, and grows
+    /// linearly to the target over `ramp_duration`, after which the policy applies as is. The ramp
+    /// starts the first time the API key's usage is checked, such as by the first acquire after it
+    /// is added to a pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key once it has warmed up.
+    /// * `start_count` - the count the ramp starts at, which is at least one.
+    /// * `ramp_duration` - how long the ramp takes to reach the policy's count.
+    pub fn with_ramp(
+        key: &str,
+        policy: RateLimitPolicy,
+        start_count: usize,
+        ramp_duration: chrono::Duration,
+    ) -> Self {
+        Self {
+            ramp: Some(Ramp {
+                start_count,
+                duration: ramp_duration,
+            }),
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns an API key with the given policy and code, which has already been used at the given
+    /// times.
+    ///
+    /// This restores the usage history of an API key, for example after a restart, so that it is
+    /// not used past its limit. Only the most recent uses that fit in the policy are kept, and uses
+    /// older than the rate limit window are forgotten as usual.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key code.
+    /// * `policy` - the rate limit policy governing the API key.
+    /// * `prior_uses` - the times the API key was previously used.
+    pub fn with_history(
+        key: &str,
+        policy: RateLimitPolicy,
+        mut prior_uses: Vec<DateTime<Utc>>,
+    ) -> Self {
+        let mut usage = Usage::new(&[policy]);
+        prior_uses.sort_unstable();
+        usage.last_used = prior_uses.last().copied();
+        let excess = prior_uses.len().saturating_sub(policy.count);
+        usage.windows[0].times.extend(prior_uses.drain(excess..));
+        Self {
+            usage: Arc::new(Mutex::new(usage)),
+            ..Self::new(key, policy)
+        }
+    }
+
+    /// Returns a new API key with the given code and the same configuration as this one, but with
+    /// an empty usage history of its own.
+    ///
+    /// The new API key keeps the policies, weight, tag, metadata, and limits of this one, but none
+    /// of its uses, cooldown, or held guards, so using either API key never counts against the
+    /// other.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_key` - the code of the new API key.
+    pub fn clone_empty(&self, new_key: &str) -> APIKey {
+        APIKey {
+            key: new_key.to_string(),
+            cooldown_until: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            usage: Arc::new(Mutex::new(Usage::new(&self.policies))),
+            ..self.share()
+        }
+    }
+
+    /// Returns a handle to the API key that shares its usage history, so that it can be used once
+    /// the pool's API keys are unlocked.
+    fn share(&self) -> APIKey {
+        APIKey {
+            key: self.key.clone(),
+            policies: self.policies.clone(),
+            weight: self.weight,
+            priority: self.priority,
+            tag: self.tag.clone(),
+            meta: self.meta.clone(),
+            enabled: self.enabled,
+            max_total_uses: self.max_total_uses,
+            cooldown_until: self.cooldown_until,
+            in_flight: Arc::clone(&self.in_flight),
+            max_concurrent: self.max_concurrent,
+            ramp: self.ramp,
+            usage: Arc::clone(&self.usage),
+            clock: Arc::clone(&self.clock),
+        }
+    }
+
+    /// Returns a copy of the API key with a usage history of its own, which starts out the same
+    /// as the API key's.
+    async fn detach(&self) -> APIKey {
+        let usage = self.usage.lock().await.clone();
+        APIKey {
+            in_flight: Arc::new(AtomicUsize::new(self.in_flight.load(Ordering::SeqCst))),
+            usage: Arc::new(Mutex::new(usage)),
+            ..self.share()
+        }
+    }
+
+    /// Checks to see if the API key belongs to the group with the given tag, which every API key
+    /// does if no tag is given.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - the tag of the group, if any.
+    fn in_group(&self, tag: Option<&str>) -> bool {
+        tag.is_none_or(|tag| self.tag.as_deref() == Some(tag))
+    }
+
+    /// Returns the code of an API key.
+    fn get_key(&self) -> String {
+        self.key.clone()
+    }
+
+    /// Replaces the rate limit policies of the API key, carrying the usage history of each old
+    /// policy over to the new policy in the same position.
+    ///
+    /// # Arguments
+    ///
+    /// * `policies` - the new rate limit policies governing the API key.
+    /// * `now` - the current time.
+    async fn set_policies(&mut self, policies: Vec<RateLimitPolicy>, now: DateTime<Utc>) {
+        let mut usage = self.usage.lock().await;
+        self.prune(&mut usage, now);
+        usage.windows.truncate(policies.len());
+        for (window, (old, new)) in usage
+            .windows
+            .iter_mut()
+            .zip(self.policies.iter().zip(&policies))
+        {
+            window.set_policy(old, new, now);
+        }
+        let kept = usage.windows.len();
+        usage
+            .windows
+            .extend(policies[kept..].iter().map(Window::new));
+        self.policies = policies;
+    }
+
+    /// Brings the usage history up to date, removing every use that has aged out of the rate limit
+    /// windows and refilling the token buckets.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked usage history of the API key.
+    /// * `now` - the current time.
+    fn prune(&self, usage: &mut Usage, now: DateTime<Utc>) {
+        usage.ramped_count = self.ramp.and_then(|ramp| {
+            let started = *usage.ramp_started.get_or_insert(now);
+            ramp.count(self.policies[0].count, now - started)
+        });
+        let policies = self.effective_policies(usage);
+        for (policy, window) in policies.iter().zip(&mut usage.windows) {
+            window.prune(policy, now);
+        }
+    }
+
+    /// Returns the rate limit policies that govern the API key right now, which differ from its
+    /// policies while it warms up.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked and pruned usage history of the API key.
+    fn effective_policies(&self, usage: &Usage) -> Cow<'_, [RateLimitPolicy]> {
+        match usage.ramped_count {
+            Some(count) => {
+                let mut policies = self.policies.clone();
+                policies[0].count = count;
+                Cow::Owned(policies)
+            }
+            None => Cow::Borrowed(&self.policies),
+        }
+    }
+
+    /// Locks the usage history of the API key to check how available it is, counting the check.
+    async fn lock_to_check(&self) -> MutexGuard<'_, Usage> {
+        #[cfg(feature = "metrics")]
+        metrics::counter!("api_key_pool_readiness_checks_total").increment(1);
+        self.usage.lock().await
+    }
+
+    /// Checks to see if the API key is available for use.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn is_ready(&self, now: DateTime<Utc>) -> bool {
+        if !self.enabled || self.is_cooling_down(now) || self.is_at_concurrency_limit() {
+            return false;
+        }
+        let mut usage = self.lock_to_check().await;
+        self.prune(&mut usage, now);
+        self.has_capacity(&usage)
+    }
+
+    /// Checks to see if the API key has as many guards held as it allows at once.
+    fn is_at_concurrency_limit(&self) -> bool {
+        self.max_concurrent
+            .is_some_and(|max| self.in_flight.load(Ordering::SeqCst) >= max)
+    }
+
+    /// Checks to see if the API key is parked following a reported failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    fn is_cooling_down(&self, now: DateTime<Utc>) -> bool {
+        self.cooldown_until.is_some_and(|until| now < until)
+    }
+
+    /// Returns how many more times the API key can be used right now, which is the fewest that any
+    /// of its policies allows.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn remaining(&self, now: DateTime<Utc>) -> usize {
+        if !self.enabled || self.is_cooling_down(now) || self.is_at_concurrency_limit() {
+            return 0;
+        }
+        let mut usage = self.lock_to_check().await;
+        self.prune(&mut usage, now);
+        let remaining = self
+            .effective_policies(&usage)
+            .iter()
+            .zip(&usage.windows)
+            .map(|(policy, window)| window.remaining(policy))
+            .min()
+            .unwrap_or(0);
+        match self.max_total_uses {
+            Some(max) => {
+                let left = max.saturating_sub(usage.total_uses);
+                remaining.min(usize::try_from(left).unwrap_or(usize::MAX))
+            }
+            None => remaining,
+        }
+    }
+
+    /// Checks to see if the API key has been handed out as many times as its lifetime allows.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked usage history of the API key.
+    fn is_exhausted(&self, usage: &Usage) -> bool {
+        self.max_total_uses
+            .is_some_and(|max| usage.total_uses >= max)
+    }
+
+    /// Returns how much of the API key's capacity is used, along with its capacity, for whichever
+    /// of its policies is the most saturated.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn load(&self, now: DateTime<Utc>) -> (f64, f64) {
+        let mut usage = self.usage.lock().await;
+        self.prune(&mut usage, now);
+        self.effective_policies(&usage)
+            .iter()
+            .zip(&usage.windows)
+            .map(|(policy, window)| window.load(policy))
+            .max_by(|a, b| (a.0 / a.1).total_cmp(&(b.0 / b.1)))
+            .unwrap_or((0.0, 1.0))
+    }
+
+    /// Checks to see if the API key is available for use, given its pruned usage history.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked and pruned usage history of the API key.
+    fn has_capacity(&self, usage: &Usage) -> bool {
+        !self.is_exhausted(usage)
+            && self
+                .effective_policies(usage)
+                .iter()
+                .zip(&usage.windows)
+                .all(|(policy, window)| window.has_capacity(policy))
+    }
+
+    /// Returns the API key code.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the primary rate limit policy that governs the API key, which is the first of its
+    /// policies.
+    pub fn policy(&self) -> RateLimitPolicy {
+        self.policies[0]
+    }
+
+    /// Returns every rate limit policy that governs the API key.
+    pub fn policies(&self) -> &[RateLimitPolicy] {
+        &self.policies
+    }
+
+    /// Returns the metadata attached to the API key, if any.
+    pub fn meta(&self) -> Option<&serde_json::Value> {
+        self.meta.as_deref()
+    }
+
+    /// Returns the times the API key was used that still count against its rate limit, oldest
+    /// first.
+    ///
+    /// For an API key governed by several policies, these are the uses that count against the
+    /// policy with the longest memory.
+    ///
+    /// These can be persisted and passed to [`APIKey::with_history`] to restore the API key.
+    pub async fn recent_uses(&self) -> Vec<DateTime<Utc>> {
+        let now = self.clock.now();
+        let mut usage = self.usage.lock().await;
+        self.prune(&mut usage, now);
+        let mut times: Vec<DateTime<Utc>> = usage
+            .windows
+            .iter()
+            .max_by_key(|window| window.times.len())
+            .map(|window| window.times.to_vec())
+            .unwrap_or_default();
+        times.sort_unstable();
+        times
+    }
+
+    /// Returns how long until the API key is available for use.
+    ///
+    /// Returns a zero duration if the API key is available now.
+    pub async fn time_until_ready(&self) -> chrono::Duration {
+        let now = self.clock.now();
+        let mut usage = self.usage.lock().await;
+        self.prune(&mut usage, now);
+        self.wait(&usage, now)
+    }
+
+    /// Returns a snapshot of the live state of the API key, describing its primary policy.
+    ///
+    /// The usage history is locked once, so that every figure describes the same moment.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn info(&self, now: DateTime<Utc>) -> KeyInfo {
+        let mut usage = self.usage.lock().await;
+        self.prune(&mut usage, now);
+        let wait = self.wait(&usage, now);
+        KeyInfo {
+            key: mask(&self.key),
+            count: usage.ramped_count.unwrap_or(self.policies[0].count),
+            per: self.policies[0].per,
+            used_slots: usage.windows[0].times.len(),
+            time_until_ready: wait,
+            next_available: (self.enabled && !self.is_exhausted(&usage)).then(|| now + wait),
+            total_uses: usage.total_uses,
+        }
+    }
+
+    /// Returns when the API key is next available for use, or `None` if it is disabled or
+    /// exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn next_available(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+        let mut usage = self.lock_to_check().await;
+        if self.is_exhausted(&usage) {
+            return None;
+        }
+        self.prune(&mut usage, now);
+        Some(now + self.wait(&usage, now))
+    }
+
+    /// Returns when the API key's rate limits and cooldown next allow a use, whether or not it is
+    /// enabled or exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn next_allowed(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let mut usage = self.lock_to_check().await;
+        self.prune(&mut usage, now);
+        now + self.wait(&usage, now)
+    }
+
+    /// Returns a lower bound on when the API key is next available for use, which holds however
+    /// far it has warmed up by then, or `None` if it is disabled or has been handed out as many
+    /// times as its lifetime allows.
+    ///
+    /// While the API key warms up, its count grows with time, so it can become available sooner
+    /// than [`APIKey::next_available`] says. The bound is when it would be available at its full
+    /// count.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    async fn next_available_bound(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+        let mut usage = self.lock_to_check().await;
+        if self.is_exhausted(&usage) {
+            return None;
+        }
+        self.prune(&mut usage, now);
+        Some(now + self.wait_under(&self.policies, &usage, now))
+    }
+
+    /// Returns how long until the API key is available for use, given its pruned usage history,
+    /// which is the longest that any of its policies or its cooldown holds it back.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked and pruned usage history of the API key.
+    /// * `now` - the current time.
+    fn wait(&self, usage: &Usage, now: DateTime<Utc>) -> chrono::Duration {
+        self.wait_under(&self.effective_policies(usage), usage, now)
+    }
+
+    /// Returns how long until the API key is available for use under the given policies, given its
+    /// pruned usage history.
+    ///
+    /// # Arguments
+    ///
+    /// * `policies` - the rate limit policies to check the usage history against.
+    /// * `usage` - the locked and pruned usage history of the API key.
+    /// * `now` - the current time.
+    fn wait_under(
+        &self,
+        policies: &[RateLimitPolicy],
+        usage: &Usage,
+        now: DateTime<Utc>,
+    ) -> chrono::Duration {
+        let cooldown = match self.cooldown_until {
+            Some(until) if now < until => until - now,
+            _ => chrono::Duration::zero(),
+        };
+        policies
+            .iter()
+            .zip(&usage.windows)
+            .map(|(policy, window)| window.wait(policy, now))
+            .fold(cooldown, chrono::Duration::max)
+    }
+
+    /// Uses the key if it is available.
+    ///
+    /// The usage history is locked once for both the readiness check and the recording of the new
+    /// use, so concurrent callers cannot both observe the same free slot. Guards are only counted
+    /// while the usage history is locked, for the same reason.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    /// * `hold` - whether to count the use as in flight until a guard for it is dropped.
+    async fn try_acquire(&self, now: DateTime<Utc>, hold: bool) -> Option<String> {
+        if !self.enabled || self.is_cooling_down(now) {
+            return None;
+        }
+        let mut usage = self.usage.lock().await;
+        self.prune(&mut usage, now);
+        if !self.has_capacity(&usage) || (hold && self.is_at_concurrency_limit()) {
+            return None;
+        }
+        if hold {
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+        }
+        self.record(&mut usage, now, now);
+        Some(self.get_key())
+    }
+
+    /// Checks to see if the API key will be available for use at the given time, given its pruned
+    /// usage history, counting every use recorded so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked and pruned usage history of the API key.
+    /// * `when` - the time the API key is to be used.
+    fn is_free_at(&self, usage: &Usage, when: DateTime<Utc>) -> bool {
+        if !self.enabled || self.cooldown_until.is_some_and(|until| when < until) {
+            return false;
+        }
+        let mut future = usage.clone();
+        self.prune(&mut future, when);
+        self.has_capacity(&future)
+    }
+
+    /// Records a use of the API key at the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - the locked and pruned usage history of the API key.
+    /// * `at` - the time of the use, which is not before the current time.
+    /// * `now` - the current time.
+    fn record(&self, usage: &mut Usage, at: DateTime<Utc>, now: DateTime<Utc>) {
+        let policies = self.effective_policies(usage).into_owned();
+        for (policy, window) in policies.iter().zip(&mut usage.windows) {
+            window.record(policy, at, now);
+        }
+        usage.total_uses += 1;
+        usage.last_used = Some(usage.last_used.map_or(at, |last_used| last_used.max(at)));
+    }
+}
+
+impl fmt::Debug for APIKey {
+    /// Formats the API key with its code masked.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("APIKey")
+            .field("key", &mask(&self.key))
+            .field("len", &self.key.chars().count())
+            .field("policies", &self.policies)
+            .field("weight", &self.weight)
+            .field("tag", &self.tag)
+            .field("meta", &self.meta)
+            .field("enabled", &self.enabled)
+            .field("max_total_uses", &self.max_total_uses)
+            .field("max_concurrent", &self.max_concurrent)
+            .field("ramp", &self.ramp)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns a masked form of an API key code that is safe to log, showing only a short suffix.
+///
+/// At most a quarter of the code, and never more than four characters, is shown.
+///
+/// # Arguments
+///
+/// * `key` - the API key code.
+fn mask(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let shown = (chars.len() / 4).min(4);
+    let suffix: String = chars[chars.len() - shown..].iter().collect();
+    format!("…{}", suffix)
+}
+
+/// Tolerance for floating-point error when checking whether a whole token has refilled.
+const TOKEN_EPSILON: f64 = 1e-9;
+
+/// The most times the cooldown of an API key doubles for consecutive failures reported with
+/// [`APIKeyPool::report_failure`].
+const MAX_COOLDOWN_DOUBLINGS: u32 = 10;
+
+/// The number of recent waits kept for [`APIKeyPool::wait_latency_percentiles`] by default.
+const DEFAULT_WAIT_SAMPLES: usize = 1024;
+
+/// The most times [`APIKeyPool::with_key`] calls its function before giving up on rate-limited
+/// API keys.
+const MAX_CALL_ATTEMPTS: usize = 5;
+
+/// The most API key probes that [`APIKeyPool::probe_all`] runs at once.
+const PROBE_CONCURRENCY: usize = 8;
+
+/// How long to wait, in milliseconds, before checking again when API keys are due but none can be
+/// chosen.
+const RECHECK_INTERVAL_MS: i64 = 100;
+
+/// A snapshot of the live state of an API key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyInfo {
+    /// The API key code, masked.
+    pub key: String,
+    /// The number of times the API key can be used in the specified duration.
+    pub count: usize,
+    /// The duration.
+    pub per: chrono::Duration,
+    /// The number of uses that count against the current rate limit window.
+    pub used_slots: usize,
+    /// How long until the API key is available for use, or zero if it is available now.
+    pub time_until_ready: chrono::Duration,
+    /// When the API key is next available for use, or `None` if it is disabled or exhausted.
+    pub next_available: Option<DateTime<Utc>>,
+    /// The number of times the API key has been handed out.
+    pub total_uses: u64,
+}
+
+/// An owned snapshot of the live state of every API key in a pool.
+///
+/// A snapshot holds no locks, so it can be inspected synchronously, for example while formatting
+/// an error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolSnapshot {
+    /// The state of each API key, in the order of the pool.
+    pub keys: Vec<KeyInfo>,
+}
+
+/// An exponential backoff, added to each sleep in [`APIKeyPool::wait_for_key`] after consecutive
+/// failed acquires.
+///
+/// After the first failed acquire the backoff is `base`, and after each further one it is
+/// multiplied by `factor`, up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    /// The backoff after the first failed acquire.
+    pub base: chrono::Duration,
+    /// How much the backoff grows after each further failed acquire.
+    pub factor: f64,
+    /// The longest backoff.
+    pub max: chrono::Duration,
+}
+
+impl Backoff {
+    /// Returns an exponential backoff.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - the backoff after the first failed acquire.
+    /// * `factor` - how much the backoff grows after each further failed acquire.
+    /// * `max` - the longest backoff.
+    pub fn new(base: chrono::Duration, factor: f64, max: chrono::Duration) -> Self {
+        Self { base, factor, max }
+    }
+
+    /// Returns the backoff after the given number of earlier consecutive failed acquires.
+    ///
+    /// # Arguments
+    ///
+    /// * `failures` - the number of earlier consecutive failed acquires.
+    fn delay(&self, failures: i32) -> chrono::Duration {
+        let base = self.base.num_nanoseconds().unwrap_or(i64::MAX) as f64;
+        let max = self.max.num_nanoseconds().unwrap_or(i64::MAX) as f64;
+        let delay = (base * self.factor.powi(failures)).min(max);
+        if delay.is_nan() {
+            return self.max.max(chrono::Duration::zero());
+        }
+        chrono::Duration::nanoseconds(delay.max(0.0) as i64)
+    }
+}
+
+/// A linear ramp of a policy's count, from a starting count up to the policy's own.
+#[derive(Clone, Copy, Debug)]
+struct Ramp {
+    /// The count the ramp starts at.
+    start_count: usize,
+    /// How long the ramp takes to reach the policy's count.
+    duration: chrono::Duration,
+}
+
+impl Ramp {
+    /// Returns the count partway through the ramp, or `None` once the ramp is over.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - the count the ramp ends at.
+    /// * `elapsed` - how long since the ramp started.
+    fn count(&self, target: usize, elapsed: chrono::Duration) -> Option<usize> {
+        if elapsed >= self.duration {
+            return None;
+        }
+        // A zero count is ramped towards one, so the bounds of the clamp never cross.
+        let target = target.max(1);
+        let start = self.start_count.clamp(1, target);
+        let progress = elapsed.as_seconds_f64().max(0.0) / self.duration.as_seconds_f64();
+        Some(start + ((target - start) as f64 * progress).floor() as usize)
+    }
+}
 
-/// A pool of API keys.
-#[derive(Default)]
-pub struct APIKeyPool {
-    /// Collection holding the API keys.
-    api_keys: Arc<Mutex<Vec<APIKey>>>,
+/// The usage history of an API key.
+#[derive(Clone)]
+struct Usage {
+    /// The usage history under each rate limit policy, in the same order as the policies.
+    windows: Vec<Window>,
+    /// The number of times the API key has been handed out.
+    total_uses: u64,
+    /// When the API key was last handed out, if ever.
+    last_used: Option<DateTime<Utc>>,
+    /// The number of requests made with the API key that were reported to have succeeded.
+    successes: u64,
+    /// The number of requests made with the API key that were reported to have failed.
+    failures: u64,
+    /// The number of requests made with the API key that were reported to have failed since the
+    /// last one reported to have succeeded.
+    consecutive_failures: u32,
+    /// The current weight of the API key under smooth weighted selection.
+    current_weight: i64,
+    /// When the API key started warming up, if it has a ramp and has been checked.
+    ramp_started: Option<DateTime<Utc>>,
+    /// The count of the primary policy while the API key warms up, as of the last prune.
+    ramped_count: Option<usize>,
 }
 
-impl APIKeyPool {
-    /// Returns an empty API key pool.
-    pub fn new() -> Self {
+impl Usage {
+    /// Returns an empty usage history, whose token buckets are filled when they are first pruned.
+    ///
+    /// # Arguments
+    ///
+    /// * `policies` - the rate limit policies governing the API key.
+    fn new(policies: &[RateLimitPolicy]) -> Self {
         Self {
-            api_keys: Arc::new(Mutex::new(Vec::new())),
+            windows: policies.iter().map(Window::new).collect(),
+            total_uses: 0,
+            last_used: None,
+            successes: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            current_weight: 0,
+            ramp_started: None,
+            ramped_count: None,
         }
     }
+}
 
-    /// Adds an API key to an API key pool.
+/// The usage history of an API key under one of its rate limit policies.
+#[derive(Clone)]
+struct Window {
+    /// The times the API key was used within the rate limit window.
+    times: Times,
+    /// The times the API key is booked to be used in later fixed windows, which only count once
+    /// their window starts.
+    later: Vec<DateTime<Utc>>,
+    /// The tokens left in the bucket, for token-bucket policies.
+    tokens: f64,
+    /// When the bucket was last refilled, for token-bucket policies, or `None` if the bucket has
+    /// yet to be filled.
+    refilled: Option<DateTime<Utc>>,
+    /// When the last use drained out of the bucket, for leaky-bucket policies, or `None` if none
+    /// has yet.
+    drained: Option<DateTime<Utc>>,
+}
+
+impl Window {
+    /// Returns an empty usage history, whose token bucket is filled when it is first pruned.
     ///
     /// # Arguments
     ///
-    /// * `key` - the API key to be added.
-    pub async fn add_key(&mut self, key: APIKey) {
-        self.api_keys.lock().await.push(key);
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    fn new(policy: &RateLimitPolicy) -> Self {
+        Self {
+            times: Times::with_capacity(policy.count),
+            later: Vec::new(),
+            tokens: 0.0,
+            refilled: None,
+            drained: None,
+        }
     }
 
-    /// Checks the API key pool for any available API keys, and returns the API key if available.
-    pub async fn poll_for_key(&mut self) -> Option<String> {
-        // TODO: Performance can be improved by keeping track of index of last used key.
-        for key in &mut self.api_keys.lock().await.iter_mut() {
-            if key.is_ready().await {
-                return Some(key.use_key().await);
+    /// Moves the usage history over from one policy to another, keeping the share of the window
+    /// that is used.
+    ///
+    /// If the window length changes, the age of every use is scaled by the ratio of the new window
+    /// to the old one, so that a use halfway through the old window is halfway through the new
+    /// one. If the number of uses allowed changes, the number of recorded uses is scaled by the
+    /// ratio of the new count to the old one, rounding up, by dropping the oldest uses. A token
+    /// bucket keeps the same share of its burst.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - the rate limit policy the usage history was kept for.
+    /// * `new` - the rate limit policy the usage history is now kept for.
+    /// * `now` - the current time.
+    fn set_policy(&mut self, old: &RateLimitPolicy, new: &RateLimitPolicy, now: DateTime<Utc>) {
+        if new.per != old.per {
+            let scale = new.per.as_seconds_f64() / old.per.as_seconds_f64();
+            let retime = |time: DateTime<Utc>| {
+                let age = (now - time).num_nanoseconds().unwrap_or(i64::MAX) as f64;
+                now - chrono::Duration::nanoseconds((age * scale).round() as i64)
+            };
+            self.times.retime(retime);
+            for time in &mut self.later {
+                *time = retime(*time);
+            }
+        }
+        // Only fixed windows put uses aside for later windows.
+        if new.algorithm != RateLimitAlgorithm::FixedWindow {
+            self.times.extend(self.later.drain(..));
+        }
+        // The remaining uses of a leaky bucket drain from now on.
+        self.drained = None;
+        // Drop the oldest uses until the same share of the window is used.
+        let len = (self.times.len() * new.count)
+            .div_ceil(old.count.max(1))
+            .min(new.count);
+        while self.times.len() > len {
+            self.times.pop_oldest();
+        }
+        // Size the usage history for the new count, releasing any room kept for a larger one.
+        self.times.fit(new.count);
+        match (old.algorithm, new.algorithm) {
+            (
+                RateLimitAlgorithm::TokenBucket { burst: old_burst },
+                RateLimitAlgorithm::TokenBucket { burst },
+            ) => {
+                self.tokens =
+                    (self.tokens * burst as f64 / old_burst.max(1) as f64).min(burst as f64);
+            }
+            // Start the bucket with the share of the window that is left.
+            (_, RateLimitAlgorithm::TokenBucket { burst }) => {
+                self.tokens = (burst as f64 * (new.count - len) as f64 / new.count as f64).floor();
+                self.refilled = Some(now);
+            }
+            _ => {}
+        }
+    }
+
+    /// Brings the usage history up to date, removing every use that has aged out of the rate limit
+    /// window and refilling the token bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    /// * `now` - the current time.
+    fn prune(&mut self, policy: &RateLimitPolicy, now: DateTime<Utc>) {
+        let window_start = policy.window_start(now);
+        if !self.later.is_empty() {
+            // Uses booked for a window that has started now count like any other.
+            let window_end = window_start + policy.per;
+            let (started, later): (Vec<_>, Vec<_>) =
+                self.later.drain(..).partition(|&at| at < window_end);
+            self.later = later;
+            self.times.extend(started);
+        }
+        while let Some(oldest) = self.times.oldest() {
+            let expired = match policy.algorithm {
+                // If the oldest time used is before the current fixed window, it no longer counts.
+                RateLimitAlgorithm::FixedWindow => oldest < window_start,
+                // If the oldest use has drained for one interval, it has leaked out.
+                RateLimitAlgorithm::LeakyBucket { .. } => {
+                    let drained = self.drain_end(policy, oldest);
+                    if drained <= now {
+                        self.drained = Some(drained);
+                    }
+                    drained <= now
+                }
+                // If the oldest time used is at least D duration ago, it no longer counts.
+                _ => oldest <= now - policy.per,
+            };
+            if !expired {
+                break;
+            }
+            self.times.pop_oldest();
+        }
+        if let RateLimitAlgorithm::TokenBucket { burst } = policy.algorithm {
+            self.tokens = match self.refilled {
+                Some(refilled) => {
+                    let elapsed = (now - refilled).as_seconds_f64().max(0.0);
+                    (self.tokens + elapsed * policy.rate()).min(burst as f64)
+                }
+                // Start the bucket with whatever the current window has left.
+                None => burst.saturating_sub(self.times.len()) as f64,
+            };
+            self.refilled = Some(now);
+        }
+    }
+
+    /// Returns how many more times the policy allows the API key to be used right now.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    fn remaining(&self, policy: &RateLimitPolicy) -> usize {
+        match policy.algorithm {
+            RateLimitAlgorithm::SlidingWindow | RateLimitAlgorithm::FixedWindow => {
+                policy.count.saturating_sub(self.times.len())
+            }
+            RateLimitAlgorithm::TokenBucket { .. } => {
+                (self.tokens + TOKEN_EPSILON).floor() as usize
+            }
+            RateLimitAlgorithm::LeakyBucket { capacity } => {
+                capacity.saturating_sub(self.times.len())
+            }
+        }
+    }
+
+    /// Returns how much of the policy's capacity is used, along with its capacity.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    fn load(&self, policy: &RateLimitPolicy) -> (f64, f64) {
+        match policy.algorithm {
+            RateLimitAlgorithm::TokenBucket { burst } => (burst as f64 - self.tokens, burst as f64),
+            RateLimitAlgorithm::LeakyBucket { capacity } => {
+                (self.times.len().min(capacity) as f64, capacity as f64)
+            }
+            _ => (
+                self.times.len().min(policy.count) as f64,
+                policy.count as f64,
+            ),
+        }
+    }
+
+    /// Checks to see if the policy allows another use.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    fn has_capacity(&self, policy: &RateLimitPolicy) -> bool {
+        match policy.algorithm {
+            // If we have used the API key less than N times in the window, we can use it again.
+            RateLimitAlgorithm::SlidingWindow | RateLimitAlgorithm::FixedWindow => {
+                self.times.len() < policy.count
+            }
+            // If a whole token has refilled, we can use it again.
+            RateLimitAlgorithm::TokenBucket { .. } => self.tokens >= 1.0 - TOKEN_EPSILON,
+            // If the bucket has room for another request, we can use it again.
+            RateLimitAlgorithm::LeakyBucket { capacity } => self.times.len() < capacity,
+        }
+    }
+
+    /// Returns how long until the policy allows another use.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    /// * `now` - the current time.
+    fn wait(&self, policy: &RateLimitPolicy, now: DateTime<Utc>) -> chrono::Duration {
+        if self.has_capacity(policy) {
+            return chrono::Duration::zero();
+        }
+        match policy.algorithm {
+            RateLimitAlgorithm::SlidingWindow => match self.times.oldest() {
+                Some(oldest) => oldest + policy.per - now,
+                None => chrono::Duration::zero(),
+            },
+            RateLimitAlgorithm::TokenBucket { .. } => {
+                let secs = (1.0 - self.tokens) / policy.rate();
+                chrono::Duration::nanoseconds((secs * 1e9).ceil() as i64)
+            }
+            // Every use is forgotten when the next window starts.
+            RateLimitAlgorithm::FixedWindow => policy.window_start(now) + policy.per - now,
+            RateLimitAlgorithm::LeakyBucket { .. } => match self.times.oldest() {
+                Some(oldest) => self.drain_end(policy, oldest) - now,
+                None => chrono::Duration::zero(),
+            },
+        }
+    }
+
+    /// Returns when the oldest use in a leaky bucket has drained out, one interval after it
+    /// started draining.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    /// * `oldest` - the time of the oldest use in the bucket.
+    fn drain_end(&self, policy: &RateLimitPolicy, oldest: DateTime<Utc>) -> DateTime<Utc> {
+        // A use starts draining once it is made and the use before it has drained.
+        self.drained.map_or(oldest, |drained| drained.max(oldest)) + policy.interval()
+    }
+
+    /// Records a use of the API key.
+    ///
+    /// A use in a later fixed window is put aside until that window starts.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - the rate limit policy the usage history is kept for.
+    /// * `at` - the time of the use, which is not before the current time.
+    /// * `now` - the current time.
+    fn record(&mut self, policy: &RateLimitPolicy, at: DateTime<Utc>, now: DateTime<Utc>) {
+        match policy.algorithm {
+            RateLimitAlgorithm::FixedWindow if at >= policy.window_start(now) + policy.per => {
+                self.later.push(at);
+                return;
             }
+            RateLimitAlgorithm::TokenBucket { .. } => self.tokens -= 1.0,
+            _ => {}
         }
-        None
+        self.times.push(at);
     }
 }
 
-/// An API key, with its associated RateLimitPolicy
-pub struct APIKey {
+/// A guard holding an API key handed out by a pool, which counts as in flight until dropped.
+pub struct KeyGuard {
     /// The API key code.
     key: String,
-    /// The rate limit policy that governs this API key.
-    policy: RateLimitPolicy,
-    /// Min-heap used to calculate if the key is available.
-    times: Arc<Mutex<BinaryHeap<Reverse<DateTime<Utc>>>>>,
+    /// The number of guards held for the API key.
+    in_flight: Arc<AtomicUsize>,
+    /// Wakes the waiting acquires of the pool the API key was handed out by, and of each pool that
+    /// fell back to it.
+    freed: Vec<Arc<Notify>>,
 }
 
-impl APIKey {
-    /// Returns an API key with the given policy and code.
+impl KeyGuard {
+    /// Returns a guard for an API key that is already counted as in flight.
     ///
     /// # Arguments
     ///
     /// * `key` - the API key code.
-    /// * `policy` - the rate limit policy governing the API key.
-    pub fn new(key: &str, policy: RateLimitPolicy) -> Self {
-        let mut _times = BinaryHeap::new();
-        _times.reserve(policy.count);
-        let times = Arc::new(Mutex::new(_times));
+    /// * `in_flight` - the number of guards held for the API key.
+    /// * `freed` - wakes the waiting acquires of the pool the API key was handed out by, and of
+    ///   each pool that fell back to it.
+    fn new(key: String, in_flight: Arc<AtomicUsize>, freed: Vec<Arc<Notify>>) -> Self {
         Self {
-            key: String::from(key),
-            policy,
-            times,
+            key,
+            in_flight,
+            freed,
         }
     }
 
-    /// Returns the code of an API key.
-    fn get_key(&self) -> String {
-        self.key.clone()
+    /// Returns the code of the API key.
+    pub fn key(&self) -> &str {
+        &self.key
     }
+}
 
-    /// Checks to see if the API key is available for use.
-    async fn is_ready(&self) -> bool {
-        // If we have used the API key less than N times, we can use it again.
-        if self.times.lock().await.len() < self.policy.count {
-            return true;
-        }
-        if let Some(oldest) = self.times.lock().await.peek() {
-            // If the oldest time used is at least D duration ago.
-            if oldest.0 < Utc::now() - self.policy.per {
-                return true;
-            }
-        }
-        false
+impl Deref for KeyGuard {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.key
     }
+}
 
-    /// Uses the key.
-    async fn use_key(&mut self) -> String {
-        if self.times.lock().await.len() >= self.policy.count {
-            self.times.lock().await.pop();
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        for freed in &self.freed {
+            freed.notify_waiters();
         }
-        self.times.lock().await.push(Reverse(Utc::now()));
-        self.get_key().clone()
     }
 }
 
+impl fmt::Debug for KeyGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyGuard")
+            .field("key", &mask(&self.key))
+            .finish_non_exhaustive()
+    }
+}
+
+/// A strategy for choosing among the available API keys of a pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelectionStrategy {
+    /// Cycle through the API keys in order, so that usage is spread evenly across the pool.
+    #[default]
+    RoundRobin,
+    /// Choose randomly among the available API keys, proportionally to their weights.
+    Weighted,
+    /// Cycle through the available API keys in proportion to their weights, interleaving them as
+    /// evenly as possible, so that weights of 3 and 1 give `A A B A` rather than random clusters.
+    ///
+    /// This is the smooth weighted round-robin used by nginx. An API key with a weight of zero is
+    /// never chosen.
+    SmoothWeighted,
+    /// Choose uniformly at random among the available API keys, regardless of their weights.
+    Random,
+    /// Choose the available API key with the highest priority, set with
+    /// [`APIKey::with_priority`], cycling through API keys of the same priority in order.
+    Priority,
+    /// Choose the available API key whose most recent use is the oldest, so that no API key is
+    /// starved.
+    LeastRecentlyUsed,
+    /// Choose the API key that has been available the longest.
+    ///
+    /// API keys are kept in an index ordered by when each is next available, so an API key is
+    /// found without checking the readiness of every API key in the pool.
+    EarliestAvailable,
+    /// Choose the available API key with the largest share of its capacity left, so that API keys
+    /// with higher limits take proportionally more traffic.
+    ///
+    /// Ties go to the API key with the most uses left.
+    CapacityProportional,
+}
+
 /// A policy for rate-limiting an API key.
-#[derive(Clone, Copy)]
+///
+/// With the `serde` feature, `per` is serialized as a whole number of seconds, so a policy whose
+/// `per` is not one cannot be serialized. A deserialized policy is checked as in
+/// [`RateLimitPolicy::try_new`], [`RateLimitPolicy::try_token_bucket`] and
+/// [`RateLimitPolicy::try_leaky_bucket`].
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "UncheckedPolicy")
+)]
 pub struct RateLimitPolicy {
     /// The number of times an API key can be used in the specified duration.
     pub count: usize,
     /// The duration.
+    #[cfg_attr(feature = "serde", serde(with = "duration_seconds"))]
     pub per: chrono::Duration,
+    /// The algorithm used to enforce the limit.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub algorithm: RateLimitAlgorithm,
 }
 
 impl RateLimitPolicy {
-    /// Returns a rate-limit policy with the parameters.
+    /// Returns a rate-limit policy with the parameters, enforced over a sliding window.
     ///
     /// # Arguments
     ///
     /// * `count` - N times
     /// * `per` - per D duration
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero or `per` is not positive. Use [`RateLimitPolicy::try_new`] to
+    /// handle invalid parameters instead.
     pub fn new(count: usize, per: chrono::Duration) -> Self {
-        Self { count, per }
+        match Self::try_new(count, per) {
+            Ok(policy) => policy,
+            Err(error) => panic!("invalid rate-limit policy: {error}"),
+        }
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced over a sliding window, or an error
+    /// naming the invalid parameter.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N times, which must not be zero
+    /// * `per` - per D duration, which must be positive
+    pub fn try_new(count: usize, per: chrono::Duration) -> Result<Self, PolicyError> {
+        if count == 0 {
+            return Err(PolicyError::ZeroCount);
+        }
+        if per <= chrono::Duration::zero() {
+            return Err(PolicyError::NonPositivePer);
+        }
+        Ok(Self {
+            count,
+            per,
+            algorithm: RateLimitAlgorithm::SlidingWindow,
+        })
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced by a token bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N tokens refilled
+    /// * `per` - per D duration
+    /// * `burst` - the capacity of the bucket
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` or `burst` is zero or `per` is not positive. Use
+    /// [`RateLimitPolicy::try_token_bucket`] to handle invalid parameters instead.
+    pub fn token_bucket(count: usize, per: chrono::Duration, burst: usize) -> Self {
+        match Self::try_token_bucket(count, per, burst) {
+            Ok(policy) => policy,
+            Err(error) => panic!("invalid rate-limit policy: {error}"),
+        }
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced by a token bucket, or an error
+    /// naming the invalid parameter.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N tokens refilled, which must not be zero
+    /// * `per` - per D duration, which must be positive
+    /// * `burst` - the capacity of the bucket, which must not be zero
+    pub fn try_token_bucket(
+        count: usize,
+        per: chrono::Duration,
+        burst: usize,
+    ) -> Result<Self, PolicyError> {
+        let policy = Self::try_new(count, per)?;
+        if burst == 0 {
+            return Err(PolicyError::ZeroBurst);
+        }
+        Ok(Self {
+            algorithm: RateLimitAlgorithm::TokenBucket { burst },
+            ..policy
+        })
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced over fixed windows aligned to the
+    /// Unix epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N times
+    /// * `per` - per D duration
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero or `per` is not positive. Use [`RateLimitPolicy::try_fixed_window`]
+    /// to handle invalid parameters instead.
+    pub fn fixed_window(count: usize, per: chrono::Duration) -> Self {
+        Self {
+            algorithm: RateLimitAlgorithm::FixedWindow,
+            ..Self::new(count, per)
+        }
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced over fixed windows aligned to the
+    /// Unix epoch, or an error naming the invalid parameter.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N times, which must not be zero
+    /// * `per` - per D duration, which must be positive
+    pub fn try_fixed_window(count: usize, per: chrono::Duration) -> Result<Self, PolicyError> {
+        Ok(Self {
+            algorithm: RateLimitAlgorithm::FixedWindow,
+            ..Self::try_new(count, per)?
+        })
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced by a leaky bucket that lets one use
+    /// through every D / N duration.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N uses drained
+    /// * `per` - per D duration
+    /// * `capacity` - how many requests can queue in the bucket
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` or `capacity` is zero or `per` is not positive. Use
+    /// [`RateLimitPolicy::try_leaky_bucket`] to handle invalid parameters instead.
+    pub fn leaky_bucket(count: usize, per: chrono::Duration, capacity: usize) -> Self {
+        match Self::try_leaky_bucket(count, per, capacity) {
+            Ok(policy) => policy,
+            Err(error) => panic!("invalid rate-limit policy: {error}"),
+        }
+    }
+
+    /// Returns a rate-limit policy with the parameters, enforced by a leaky bucket, or an error
+    /// naming the invalid parameter.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - N uses drained, which must not be zero
+    /// * `per` - per D duration, which must be positive
+    /// * `capacity` - how many requests can queue in the bucket, which must not be zero
+    pub fn try_leaky_bucket(
+        count: usize,
+        per: chrono::Duration,
+        capacity: usize,
+    ) -> Result<Self, PolicyError> {
+        let policy = Self::try_new(count, per)?;
+        if capacity == 0 {
+            return Err(PolicyError::ZeroCapacity);
+        }
+        Ok(Self {
+            algorithm: RateLimitAlgorithm::LeakyBucket { capacity },
+            ..policy
+        })
+    }
+
+    /// Returns the steady interval between uses, D / N duration.
+    fn interval(&self) -> chrono::Duration {
+        let per = self.per.num_nanoseconds().unwrap_or(i64::MAX);
+        let count = i64::try_from(self.count).unwrap_or(i64::MAX).max(1);
+        chrono::Duration::nanoseconds(per / count)
+    }
+
+    /// Returns the average number of uses allowed per second.
+    fn rate(&self) -> f64 {
+        self.count as f64 / self.per.as_seconds_f64()
+    }
+
+    /// Returns when the fixed window containing the given time started, with windows aligned to the
+    /// Unix epoch.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the current time.
+    fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let per = self.per.num_nanoseconds().unwrap_or(i64::MAX).max(1);
+        let elapsed = now.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        DateTime::from_timestamp_nanos(elapsed - elapsed.rem_euclid(per))
+    }
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = ParsePolicyError;
+
+    /// Parses a sliding-window policy from shorthand such as `100/60s`, `5/1m`, or `1000/1h`.
+    ///
+    /// The duration is a whole number followed by a unit of `ms`, `s`, `m`, `h`, or `d`. The number
+    /// may be left out to mean one, as in `10/s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, per) = s
+            .split_once('/')
+            .ok_or(ParsePolicyError::MissingSeparator)?;
+        let count = count
+            .trim()
+            .parse()
+            .map_err(|_| ParsePolicyError::InvalidCount)?;
+        let per = per.trim();
+        let split = per
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or(ParsePolicyError::UnknownUnit)?;
+        let (amount, unit) = per.split_at(split);
+        let amount: i64 = match amount {
+            "" => 1,
+            _ => amount
+                .parse()
+                .map_err(|_| ParsePolicyError::InvalidDuration)?,
+        };
+        let per = match unit {
+            "ms" => chrono::Duration::try_milliseconds(amount),
+            "s" => chrono::Duration::try_seconds(amount),
+            "m" => chrono::Duration::try_minutes(amount),
+            "h" => chrono::Duration::try_hours(amount),
+            "d" => chrono::Duration::try_days(amount),
+            _ => return Err(ParsePolicyError::UnknownUnit),
+        }
+        .ok_or(ParsePolicyError::InvalidDuration)?;
+        Self::try_new(count, per).map_err(|error| match error {
+            PolicyError::ZeroCount | PolicyError::ZeroBurst | PolicyError::ZeroCapacity => {
+                ParsePolicyError::InvalidCount
+            }
+            PolicyError::NonPositivePer => ParsePolicyError::InvalidDuration,
+        })
+    }
+}
+
+impl fmt::Display for RateLimitPolicy {
+    /// Formats the policy as shorthand such as `5 / 2s`, followed by the algorithm unless it is a
+    /// sliding window, as in `10 / 1s (token bucket, burst 20)`.
+    ///
+    /// The duration is shown in the largest unit of `d`, `h`, `m`, `s`, or `ms` that it is a whole
+    /// number of, or in `ns` otherwise.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nanos = self.per.num_nanoseconds().unwrap_or(i64::MAX);
+        let (per, unit) = [
+            (86_400_000_000_000, "d"),
+            (3_600_000_000_000, "h"),
+            (60_000_000_000, "m"),
+            (1_000_000_000, "s"),
+            (1_000_000, "ms"),
+        ]
+        .into_iter()
+        .find(|(unit_nanos, _)| nanos % unit_nanos == 0)
+        .map_or((nanos, "ns"), |(unit_nanos, unit)| {
+            (nanos / unit_nanos, unit)
+        });
+        write!(f, "{} / {}{}", self.count, per, unit)?;
+        match self.algorithm {
+            RateLimitAlgorithm::SlidingWindow => Ok(()),
+            RateLimitAlgorithm::TokenBucket { burst } => {
+                write!(f, " (token bucket, burst {})", burst)
+            }
+            RateLimitAlgorithm::FixedWindow => write!(f, " (fixed window)"),
+            RateLimitAlgorithm::LeakyBucket { capacity } => {
+                write!(f, " (leaky bucket, capacity {})", capacity)
+            }
+        }
+    }
+}
+
+/// An error returned when the parameters of a rate-limit policy are invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyError {
+    /// The `count` is zero, so an API key could never be used.
+    ZeroCount,
+    /// The `per` duration is zero or negative.
+    NonPositivePer,
+    /// The `burst` of a token bucket is zero, so the bucket could never hold a token.
+    ZeroBurst,
+    /// The `capacity` of a leaky bucket is zero, so the bucket could never hold a request.
+    ZeroCapacity,
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::ZeroCount => write!(f, "`count` must not be zero"),
+            PolicyError::NonPositivePer => write!(f, "`per` must be a positive duration"),
+            PolicyError::ZeroBurst => write!(f, "`burst` must not be zero"),
+            PolicyError::ZeroCapacity => write!(f, "`capacity` must not be zero"),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// An error returned when a rate-limit policy cannot be parsed from shorthand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParsePolicyError {
+    /// The shorthand has no `/` between the count and the duration.
+    MissingSeparator,
+    /// The count is not a positive whole number.
+    InvalidCount,
+    /// The duration is not a positive whole number of its unit.
+    InvalidDuration,
+    /// The duration has no unit, or a unit other than `ms`, `s`, `m`, `h`, or `d`.
+    UnknownUnit,
+}
+
+impl fmt::Display for ParsePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsePolicyError::MissingSeparator => {
+                write!(f, "expected a policy of the form `count/duration`")
+            }
+            ParsePolicyError::InvalidCount => write!(f, "the count is not a positive whole number"),
+            ParsePolicyError::InvalidDuration => {
+                write!(f, "the duration is not a positive whole number")
+            }
+            ParsePolicyError::UnknownUnit => {
+                write!(
+                    f,
+                    "the duration unit must be one of `ms`, `s`, `m`, `h`, or `d`"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParsePolicyError {}
+
+/// An algorithm for enforcing a rate-limit policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RateLimitAlgorithm {
+    /// Allow N uses within any window of D duration.
+    #[default]
+    SlidingWindow,
+    /// Refill tokens continuously at N per D duration, up to `burst` tokens, and spend one token
+    /// per use.
+    TokenBucket {
+        /// The capacity of the bucket.
+        burst: usize,
+    },
+    /// Allow N uses within each window of D duration, with windows aligned to the Unix epoch, so
+    /// that every use is forgotten at once when a window ends.
+    FixedWindow,
+    /// Let one use drain out of the bucket every D / N duration, however bursty the requests, so
+    /// that uses are evenly spaced once the bucket is full.
+    ///
+    /// Conceptually, requests queue in the bucket and drain at a steady rate. Up to `capacity`
+    /// uses are let through back to back, and each further use waits for one to drain, so a
+    /// capacity of one spaces out every use. The pool does not hold requests itself; callers of
+    /// [`APIKeyPool::wait_for_key`] wait their turn instead.
+    LeakyBucket {
+        /// How many requests can queue in the bucket.
+        capacity: usize,
+    },
+}
+
+/// A rate-limit policy as deserialized, before its parameters are checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct UncheckedPolicy {
+    /// The number of times an API key can be used in the specified duration.
+    count: usize,
+    /// The duration.
+    #[serde(with = "duration_seconds")]
+    per: chrono::Duration,
+    /// The algorithm used to enforce the limit.
+    #[serde(default)]
+    algorithm: RateLimitAlgorithm,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedPolicy> for RateLimitPolicy {
+    type Error = PolicyError;
+
+    fn try_from(policy: UncheckedPolicy) -> Result<Self, Self::Error> {
+        match policy.algorithm {
+            RateLimitAlgorithm::TokenBucket { burst } => {
+                Self::try_token_bucket(policy.count, policy.per, burst)
+            }
+            RateLimitAlgorithm::LeakyBucket { capacity } => {
+                Self::try_leaky_bucket(policy.count, policy.per, capacity)
+            }
+            algorithm => Ok(Self {
+                algorithm,
+                ..Self::try_new(policy.count, policy.per)?
+            }),
+        }
+    }
+}
+
+/// Serialization of a duration as a whole number of seconds.
+#[cfg(feature = "serde")]
+mod duration_seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &chrono::Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if duration.subsec_nanos() != 0 {
+            return Err(serde::ser::Error::custom(
+                "duration is not a whole number of seconds",
+            ));
+        }
+        serializer.serialize_i64(duration.num_seconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<chrono::Duration, D::Error> {
+        let seconds = i64::deserialize(deserializer)?;
+        chrono::Duration::try_seconds(seconds)
+            .ok_or_else(|| serde::de::Error::custom("duration out of range"))
+    }
+}
+
+/// A plain definition of an API key, suitable for configuration files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyConfig {
+    /// The API key code.
+    pub key: String,
+    /// The number of times the API key can be used in the specified duration.
+    pub count: usize,
+    /// The duration, in seconds.
+    pub per_seconds: i64,
+}
+
+impl TryFrom<KeyConfig> for APIKey {
+    type Error = PolicyError;
+
+    /// Returns the configured API key, or an error naming the invalid parameter if `count` is
+    /// zero or `per_seconds` is not positive.
+    fn try_from(config: KeyConfig) -> Result<Self, Self::Error> {
+        let per = chrono::Duration::try_seconds(config.per_seconds).unwrap_or(
+            if config.per_seconds < 0 {
+                chrono::TimeDelta::MIN
+            } else {
+                chrono::TimeDelta::MAX
+            },
+        );
+        let policy = RateLimitPolicy::try_new(config.count, per)?;
+        Ok(APIKey::new(&config.key, policy))
+    }
+}
+
+/// An error returned when an API key cannot be acquired from a pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolError {
+    /// The pool has no API keys.
+    Empty,
+    /// Every enabled API key in the pool is currently rate-limited.
+    AllBusy {
+        /// How long until the soonest API key is available.
+        retry_after: chrono::Duration,
+    },
+    /// Every API key in the pool is disabled.
+    AllDisabled,
+    /// The pool has been shut down.
+    ShutDown,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Empty => write!(f, "the API key pool is empty"),
+            PoolError::AllDisabled => write!(f, "all API keys are disabled"),
+            PoolError::ShutDown => write!(f, "the API key pool is shut down"),
+            PoolError::AllBusy { retry_after } => write!(
+                f,
+                "all API keys are busy, retry after {}ms",
+                retry_after.num_milliseconds()
+            ),
+        }
+    }
+}
+
+impl PoolError {
+    /// Returns the error for two pools that both failed to hand out an API key, preferring the one
+    /// that will have an API key available soonest.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - the error from the other pool.
+    fn or(self, other: PoolError) -> PoolError {
+        match (self, other) {
+            (PoolError::AllBusy { retry_after: a }, PoolError::AllBusy { retry_after: b }) => {
+                PoolError::AllBusy {
+                    retry_after: a.min(b),
+                }
+            }
+            (PoolError::AllBusy { .. }, _) => self,
+            (_, PoolError::AllBusy { .. }) => other,
+            (PoolError::AllDisabled, _) | (_, PoolError::AllDisabled) => PoolError::AllDisabled,
+            (PoolError::ShutDown, error) | (error, PoolError::ShutDown) => error,
+            (PoolError::Empty, PoolError::Empty) => PoolError::Empty,
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+/// An error returned by a request made with an API key in [`APIKeyPool::with_key`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallError<E> {
+    /// The API key was rate-limited by the provider, so the request should be retried with another
+    /// API key.
+    RateLimited {
+        /// How long the API key should be parked for.
+        retry_after: chrono::Duration,
+    },
+    /// The request failed for another reason.
+    Failed(E),
+}
+
+impl<E: fmt::Display> fmt::Display for CallError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::RateLimited { retry_after } => write!(
+                f,
+                "the API key was rate-limited, retry after {}ms",
+                retry_after.num_milliseconds()
+            ),
+            CallError::Failed(error) => error.fmt(f),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for CallError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CallError::RateLimited { .. } => None,
+            CallError::Failed(error) => Some(error),
+        }
     }
 }
@@ -0,0 +1,39 @@
+//! Macros for declaring API key pools inline.
+
+/// Declares an API key pool whose API keys share a rate limit policy.
+///
+/// Each API key code may be followed by `=>` and a policy of its own, which overrides the shared
+/// policy for that API key.
+///
+/// # Example
+///
+/// ```
+/// use chrono::Duration;
+///
+/// use api_key_pool::*;
+///
+/// let pool = key_pool!(
+///     policy = RateLimitPolicy::new(5, Duration::seconds(1));
+///     "k1",
+///     "k2",
+///     "k3" => RateLimitPolicy::new(10, Duration::seconds(1)),
+/// );
+/// ```
+#[macro_export]
+macro_rules! key_pool {
+    (@policy $default:ident) => {
+        $default
+    };
+    (@policy $default:ident, $policy:expr) => {
+        $policy
+    };
+    (policy = $policy:expr; $($key:expr $(=> $key_policy:expr)?),* $(,)?) => {{
+        let policy: $crate::RateLimitPolicy = $policy;
+        $crate::APIKeyPool::builder()
+            $(.with_key($crate::APIKey::new(
+                $key,
+                $crate::key_pool!(@policy policy $(, $key_policy)?),
+            )))*
+            .build()
+    }};
+}
@@ -0,0 +1,180 @@
+//! A common interface for sources of API keys, so that other backends can stand in for an
+//! in-memory pool.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::time;
+
+use crate::{APIKey, APIKeyPool, CallError, PoolError, MAX_CALL_ATTEMPTS, RECHECK_INTERVAL_MS};
+
+/// A future returned by a [`KeyProvider`], boxed so that the trait can be used as a trait object.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A source of rate-limited API keys.
+///
+/// [`APIKeyPool`] keeps its API keys in memory, but a backend that keeps them elsewhere, such as
+/// in Redis to share rate limits across processes, can implement this trait and be used wherever a
+/// `dyn KeyProvider` is expected.
+pub trait KeyProvider: Send + Sync {
+    /// Returns an available API key, using it, or the reason none is available.
+    fn try_acquire(&self) -> BoxFuture<'_, Result<String, PoolError>>;
+
+    /// Adds an API key to the provider.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the API key to be added.
+    fn add_key(&self, key: APIKey) -> BoxFuture<'_, ()>;
+
+    /// Returns the number of API keys in the provider.
+    fn len(&self) -> BoxFuture<'_, usize>;
+
+    /// Reports that a request made with an API key succeeded.
+    ///
+    /// By default, this does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key that succeeded.
+    fn report_success<'a>(&'a self, key: &'a str) -> BoxFuture<'a, ()> {
+        let _ = key;
+        Box::pin(async {})
+    }
+
+    /// Reports that a request made with an API key was rate-limited, so that the API key can be
+    /// parked for a cooldown.
+    ///
+    /// By default, this does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the code of the API key that failed.
+    /// * `cooldown` - how long the API key should be parked for.
+    fn report_failure<'a>(&'a self, key: &'a str, cooldown: chrono::Duration) -> BoxFuture<'a, ()> {
+        let _ = (key, cooldown);
+        Box::pin(async {})
+    }
+
+    /// Checks to see if the provider has no API keys.
+    fn is_empty(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async move { self.len().await == 0 })
+    }
+
+    /// Waits until an API key is available, and returns it, or returns `None` if the provider is
+    /// shut down first.
+    ///
+    /// By default, this tries again once the soonest API key is due, and checks an empty provider
+    /// or one whose API keys are all disabled again on a short interval.
+    fn wait_for_key(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async move {
+            let recheck = chrono::Duration::milliseconds(RECHECK_INTERVAL_MS);
+            loop {
+                let sleep = match self.try_acquire().await {
+                    Ok(key) => return Some(key),
+                    Err(PoolError::AllBusy { retry_after }) => retry_after,
+                    Err(PoolError::Empty | PoolError::AllDisabled) => recheck,
+                    Err(PoolError::ShutDown) => return None,
+                };
+                time::sleep(sleep.to_std().unwrap_or_default()).await;
+            }
+        })
+    }
+}
+
+impl KeyProvider for APIKeyPool {
+    fn try_acquire(&self) -> BoxFuture<'_, Result<String, PoolError>> {
+        Box::pin(APIKeyPool::try_acquire(self))
+    }
+
+    fn add_key(&self, key: APIKey) -> BoxFuture<'_, ()> {
+        Box::pin(APIKeyPool::add_key(self, key))
+    }
+
+    fn len(&self) -> BoxFuture<'_, usize> {
+        Box::pin(APIKeyPool::len(self))
+    }
+
+    fn is_empty(&self) -> BoxFuture<'_, bool> {
+        Box::pin(APIKeyPool::is_empty(self))
+    }
+
+    fn report_success<'a>(&'a self, key: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(APIKeyPool::report_success(self, key))
+    }
+
+    fn report_failure<'a>(&'a self, key: &'a str, cooldown: chrono::Duration) -> BoxFuture<'a, ()> {
+        Box::pin(APIKeyPool::report_failure(self, key, cooldown))
+    }
+
+    fn wait_for_key(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(APIKeyPool::wait_for_key(self))
+    }
+}
+
+impl<P: KeyProvider + ?Sized> KeyProvider for Arc<P> {
+    fn try_acquire(&self) -> BoxFuture<'_, Result<String, PoolError>> {
+        (**self).try_acquire()
+    }
+
+    fn add_key(&self, key: APIKey) -> BoxFuture<'_, ()> {
+        (**self).add_key(key)
+    }
+
+    fn len(&self) -> BoxFuture<'_, usize> {
+        (**self).len()
+    }
+
+    fn is_empty(&self) -> BoxFuture<'_, bool> {
+        (**self).is_empty()
+    }
+
+    fn report_success<'a>(&'a self, key: &'a str) -> BoxFuture<'a, ()> {
+        (**self).report_success(key)
+    }
+
+    fn report_failure<'a>(&'a self, key: &'a str, cooldown: chrono::Duration) -> BoxFuture<'a, ()> {
+        (**self).report_failure(key, cooldown)
+    }
+
+    fn wait_for_key(&self) -> BoxFuture<'_, Option<String>> {
+        (**self).wait_for_key()
+    }
+}
+
+/// Waits until the provider has an API key available, calls the given function with it, and
+/// returns the function's result, as in [`APIKeyPool::with_key`].
+///
+/// # Arguments
+///
+/// * `provider` - the source of the API keys.
+/// * `f` - the function making a request with an API key.
+pub(crate) async fn with_key<P, F, Fut, T, E>(provider: &P, mut f: F) -> Result<T, E>
+where
+    P: KeyProvider + ?Sized,
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T, CallError<E>>>,
+    E: From<PoolError>,
+{
+    let mut retry_after = chrono::Duration::zero();
+    for _ in 0..MAX_CALL_ATTEMPTS {
+        let Some(key) = provider.wait_for_key().await else {
+            return Err(PoolError::ShutDown.into());
+        };
+        match f(key.clone()).await {
+            Ok(value) => {
+                provider.report_success(&key).await;
+                return Ok(value);
+            }
+            Err(CallError::RateLimited {
+                retry_after: cooldown,
+            }) => {
+                provider.report_failure(&key, cooldown).await;
+                retry_after = cooldown;
+            }
+            Err(CallError::Failed(error)) => return Err(error),
+        }
+    }
+    Err(PoolError::AllBusy { retry_after }.into())
+}
@@ -0,0 +1,57 @@
+//! A stream of API keys, paced to the rate limits of a pool.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio_stream::Stream;
+
+use crate::APIKeyPool;
+
+/// A pending wait for the next API key, which hands the pool back along with the key, or `None`
+/// once the pool is shut down.
+type Acquire = Pin<Box<dyn Future<Output = (APIKeyPool, Option<String>)> + Send>>;
+
+/// A stream that yields an API key whenever one is available.
+pub(crate) struct KeyStream {
+    /// The wait for the next API key, or `None` once the stream has ended.
+    acquire: Option<Acquire>,
+}
+
+impl KeyStream {
+    /// Returns a stream of API keys from the given pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - the API key pool.
+    pub(crate) fn new(pool: APIKeyPool) -> Self {
+        Self {
+            acquire: Some(Self::acquire(pool)),
+        }
+    }
+
+    /// Returns a wait for the next API key from the given pool.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - the API key pool.
+    fn acquire(pool: APIKeyPool) -> Acquire {
+        Box::pin(async move {
+            let key = pool.wait_for_key().await;
+            (pool, key)
+        })
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(acquire) = self.acquire.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let (pool, key) = ready!(acquire.as_mut().poll(cx));
+        self.acquire = key.is_some().then(|| Self::acquire(pool));
+        Poll::Ready(key)
+    }
+}
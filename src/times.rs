@@ -0,0 +1,178 @@
+//! The times an API key was used within a rate limit window, stored inline while there are few.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use chrono::{DateTime, Utc};
+
+/// The most times stored inline, without a heap allocation.
+pub(crate) const INLINE_CAPACITY: usize = 8;
+
+/// The times an API key was used within a rate limit window, kept unordered inline for a count of
+/// at most [`INLINE_CAPACITY`] and in a min-heap otherwise.
+#[derive(Clone)]
+pub(crate) enum Times {
+    /// Times stored inline, unordered, of which the first `len` are in use.
+    Inline {
+        /// The times, of which only the first `len` are meaningful.
+        times: [DateTime<Utc>; INLINE_CAPACITY],
+        /// The number of times stored.
+        len: usize,
+    },
+    /// Times stored in a min-heap.
+    Heap(BinaryHeap<Reverse<DateTime<Utc>>>),
+}
+
+impl Times {
+    /// Returns empty storage with room for the given number of times.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - the number of times to make room for.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        if capacity <= INLINE_CAPACITY {
+            Self::inline()
+        } else {
+            Times::Heap(BinaryHeap::with_capacity(capacity))
+        }
+    }
+
+    /// Returns empty inline storage.
+    fn inline() -> Self {
+        Times::Inline {
+            times: [DateTime::<Utc>::MIN_UTC; INLINE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of times stored.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Times::Inline { len, .. } => *len,
+            Times::Heap(heap) => heap.len(),
+        }
+    }
+
+    /// Returns the oldest time stored, if any.
+    pub(crate) fn oldest(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Times::Inline { times, len } => times[..*len].iter().min().copied(),
+            Times::Heap(heap) => heap.peek().map(|time| time.0),
+        }
+    }
+
+    /// Removes the oldest time stored, if any.
+    pub(crate) fn pop_oldest(&mut self) {
+        match self {
+            Times::Inline { times, len } => {
+                let oldest = (0..*len).min_by_key(|&i| times[i]);
+                if let Some(i) = oldest {
+                    *len -= 1;
+                    times[i] = times[*len];
+                }
+            }
+            Times::Heap(heap) => {
+                heap.pop();
+            }
+        }
+    }
+
+    /// Stores a time, moving the times to a heap if the inline array is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - the time to be stored.
+    pub(crate) fn push(&mut self, time: DateTime<Utc>) {
+        if let Times::Inline { len, .. } = self {
+            if *len == INLINE_CAPACITY {
+                self.move_to_heap(INLINE_CAPACITY * 2);
+            }
+        }
+        match self {
+            Times::Inline { times, len } => {
+                times[*len] = time;
+                *len += 1;
+            }
+            Times::Heap(heap) => heap.push(Reverse(time)),
+        }
+    }
+
+    /// Returns every time stored, in no particular order.
+    pub(crate) fn to_vec(&self) -> Vec<DateTime<Utc>> {
+        match self {
+            Times::Inline { times, len } => times[..*len].to_vec(),
+            Times::Heap(heap) => heap.iter().map(|time| time.0).collect(),
+        }
+    }
+
+    /// Replaces every time stored with the result of the given function.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - the function mapping each old time to its new time.
+    pub(crate) fn retime(&mut self, f: impl Fn(DateTime<Utc>) -> DateTime<Utc>) {
+        match self {
+            Times::Inline { times, len } => {
+                for time in &mut times[..*len] {
+                    *time = f(*time);
+                }
+            }
+            Times::Heap(heap) => {
+                *heap = heap.drain().map(|time| Reverse(f(time.0))).collect();
+            }
+        }
+    }
+
+    /// Sizes the storage to hold the given number of times without reallocating, releasing any
+    /// room kept for more, and storing the times inline if they fit.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - the number of times to make room for.
+    pub(crate) fn fit(&mut self, capacity: usize) {
+        let capacity = capacity.max(self.len());
+        if capacity <= INLINE_CAPACITY {
+            if let Times::Heap(heap) = self {
+                let mut inline = Self::inline();
+                for time in heap.drain() {
+                    inline.push(time.0);
+                }
+                *self = inline;
+            }
+            return;
+        }
+        match self {
+            Times::Inline { .. } => self.move_to_heap(capacity),
+            Times::Heap(heap) => {
+                heap.shrink_to(capacity);
+                heap.reserve(capacity - heap.len());
+            }
+        }
+    }
+
+    /// Releases any room kept for times not stored yet.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.fit(0);
+    }
+
+    /// Moves the times to a heap with room for the given number of times.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - the number of times to make room for.
+    fn move_to_heap(&mut self, capacity: usize) {
+        if let Times::Inline { times, len } = self {
+            let mut heap = BinaryHeap::with_capacity(capacity.max(*len));
+            heap.extend(times[..*len].iter().map(|&time| Reverse(time)));
+            *self = Times::Heap(heap);
+        }
+    }
+}
+
+impl Extend<DateTime<Utc>> for Times {
+    fn extend<I: IntoIterator<Item = DateTime<Utc>>>(&mut self, iter: I) {
+        for time in iter {
+            self.push(time);
+        }
+    }
+}
@@ -0,0 +1,711 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+
+use chrono::{Duration, TimeZone, Utc};
+
+use api_key_pool::*;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_acquires_hand_out_a_single_slot_once() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(1, Duration::seconds(10)),
+    ))
+    .await;
+
+    let mut tasks = Vec::new();
+    for _ in 0..64 {
+        let pool = pool.clone();
+        tasks.push(tokio::spawn(async move { pool.poll_for_key().await }));
+    }
+    let mut handed_out = 0;
+    for task in tasks {
+        if task.await.unwrap().is_some() {
+            handed_out += 1;
+        }
+    }
+    assert_eq!(handed_out, 1);
+}
+
+#[tokio::test]
+async fn try_acquire_tells_an_empty_pool_from_a_busy_one() {
+    let pool = APIKeyPool::new();
+    assert_eq!(pool.try_acquire().await, Err(PoolError::Empty));
+
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(1, Duration::seconds(10)),
+    ))
+    .await;
+    assert_eq!(pool.try_acquire().await.unwrap(), "a");
+    let Err(PoolError::AllBusy { retry_after }) = pool.try_acquire().await else {
+        panic!("the only key should be busy");
+    };
+    assert!(retry_after > Duration::zero() && retry_after <= Duration::seconds(10));
+    assert_eq!(pool.poll_for_key().await, None);
+}
+
+fn policy() -> RateLimitPolicy {
+    RateLimitPolicy::new(10, Duration::seconds(1))
+}
+
+#[tokio::test]
+async fn disabled_key_is_skipped_until_reenabled() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+    assert!(pool.set_enabled("a", false).await);
+
+    for _ in 0..5 {
+        assert_eq!(pool.poll_for_key().await.as_deref(), Some("b"));
+    }
+
+    assert!(pool.set_enabled("a", true).await);
+    let mut keys = Vec::new();
+    for _ in 0..2 {
+        keys.push(pool.poll_for_key().await.unwrap());
+    }
+    assert!(keys.iter().any(|key| key == "a"), "{keys:?}");
+}
+
+#[tokio::test]
+async fn cooling_down_key_is_skipped_until_the_cooldown_ends() {
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new("a", policy()))
+        .with_key(APIKey::new("b", policy()))
+        .build();
+    pool.report_failure("a", Duration::seconds(30)).await;
+
+    for _ in 0..5 {
+        assert_eq!(pool.poll_for_key().await.as_deref(), Some("b"));
+        clock.advance(Duration::seconds(1));
+    }
+    pool.set_enabled("b", false).await;
+    assert_eq!(
+        pool.try_acquire().await,
+        Err(PoolError::AllBusy {
+            retry_after: Duration::seconds(25)
+        })
+    );
+
+    clock.advance(Duration::seconds(25));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn len_and_acquire_run_concurrently() {
+    let pool: APIKeyPool = (0..8)
+        .map(|i| {
+            APIKey::new(
+                &format!("key-{i}"),
+                RateLimitPolicy::new(1000, Duration::hours(1)),
+            )
+        })
+        .collect();
+
+    let mut tasks = Vec::new();
+    for i in 0..16 {
+        let pool = pool.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..100 {
+                if i % 2 == 0 {
+                    assert_eq!(pool.len().await, 8);
+                } else {
+                    assert!(pool.poll_for_key().await.is_some());
+                }
+            }
+        }));
+    }
+    let all = async {
+        for task in tasks {
+            task.await.unwrap();
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(10), all)
+        .await
+        .expect("len and acquires deadlocked");
+}
+
+#[tokio::test]
+async fn guard_counts_as_in_flight_until_dropped() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+    assert_eq!(pool.in_flight("a").await, Some(0));
+
+    let guard = pool.acquire_guard().await.unwrap();
+    assert_eq!(guard.key(), "a");
+    assert_eq!(pool.in_flight("a").await, Some(1));
+
+    drop(guard);
+    assert_eq!(pool.in_flight("a").await, Some(0));
+    assert_eq!(pool.in_flight("missing").await, None);
+}
+
+#[tokio::test]
+async fn peek_key_uses_nothing() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+    for _ in 0..3 {
+        assert_eq!(pool.peek_key().await.as_deref(), Some("a"));
+    }
+    assert_eq!(
+        pool.usage_stats().await,
+        [("a".to_string(), 0), ("b".to_string(), 0)]
+    );
+    assert_eq!(pool.key_info("a").await.unwrap().used_slots, 0);
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+}
+
+#[tokio::test]
+async fn session_sticks_to_its_key_while_ready() {
+    let pool: APIKeyPool = ["a", "b", "c", "d"]
+        .into_iter()
+        .map(|key| APIKey::new(key, RateLimitPolicy::new(5, Duration::hours(1))))
+        .collect();
+
+    let first = pool.acquire_for("session-42").await.unwrap();
+    for _ in 0..4 {
+        assert_eq!(pool.acquire_for("session-42").await.unwrap(), first);
+    }
+    // Once its API key is used up, the session falls back to another one.
+    let next = pool.acquire_for("session-42").await.unwrap();
+    assert_ne!(next, first);
+}
+
+#[tokio::test]
+async fn with_key_retries_a_rate_limited_key_with_another() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+
+    let mut tried = Vec::new();
+    let result: Result<String, PoolError> = pool
+        .with_key(|key| {
+            tried.push(key.clone());
+            async move {
+                match key.as_str() {
+                    "a" => Err(CallError::RateLimited {
+                        retry_after: Duration::minutes(1),
+                    }),
+                    _ => Ok(format!("response from {key}")),
+                }
+            }
+        })
+        .await;
+    assert_eq!(result.as_deref(), Ok("response from b"));
+    assert_eq!(tried, ["a", "b"]);
+    assert_eq!(pool.reliability("a").await, Some(0.0));
+    assert_eq!(pool.reliability("b").await, Some(1.0));
+}
+
+#[tokio::test]
+async fn with_key_gives_up_on_persistent_rate_limits() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+
+    let mut calls = 0;
+    let result: Result<(), PoolError> = pool
+        .with_key(|_| {
+            calls += 1;
+            async {
+                Err(CallError::RateLimited {
+                    retry_after: Duration::zero(),
+                })
+            }
+        })
+        .await;
+    assert_eq!(
+        result,
+        Err(PoolError::AllBusy {
+            retry_after: Duration::zero()
+        })
+    );
+    assert_eq!(calls, 5);
+}
+
+#[tokio::test]
+async fn saturated_pool_falls_back() {
+    let fallback = APIKeyPool::new();
+    fallback.add_key(APIKey::new("backup", policy())).await;
+    let pool = APIKeyPool::new().with_fallback(fallback.clone());
+    pool.add_key(APIKey::new(
+        "primary",
+        RateLimitPolicy::new(1, Duration::minutes(1)),
+    ))
+    .await;
+
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("primary"));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("backup"));
+    assert_eq!(fallback.usage_stats().await, [("backup".to_string(), 1)]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn releasing_a_fallback_key_wakes_the_waiters_of_the_pool_that_fell_back() {
+    let fallback = APIKeyPool::new();
+    fallback
+        .add_key(APIKey::with_max_concurrent("backup", policy(), 1))
+        .await;
+    let pool = APIKeyPool::new().with_fallback(fallback);
+    pool.add_key(APIKey::new(
+        "primary",
+        RateLimitPolicy::new(1, Duration::hours(1)),
+    ))
+    .await;
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("primary"));
+    let guard = pool.acquire_guard().await.unwrap();
+    assert_eq!(guard.key(), "backup");
+
+    let waiter = tokio::spawn({
+        let pool = pool.clone();
+        async move {
+            let key = pool.wait_for_key().await;
+            (key, tokio::time::Instant::now())
+        }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(1050)).await;
+    let released = tokio::time::Instant::now();
+    drop(guard);
+
+    // The waiter wakes as soon as the guard is dropped, rather than on its next recheck.
+    let (key, acquired) = waiter.await.unwrap();
+    assert_eq!(key.as_deref(), Some("backup"));
+    assert_eq!(acquired, released);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_acquires_respect_every_limit() {
+    let pool: APIKeyPool = ["a", "b", "c"]
+        .into_iter()
+        .map(|key| APIKey::new(key, RateLimitPolicy::new(5, Duration::hours(1))))
+        .collect();
+
+    let mut tasks = Vec::new();
+    for _ in 0..32 {
+        let pool = pool.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut acquired = 0;
+            for _ in 0..2 {
+                if pool.poll_for_key().await.is_some() {
+                    acquired += 1;
+                }
+            }
+            acquired
+        }));
+    }
+    let all = async {
+        let mut total = 0;
+        for task in tasks {
+            total += task.await.unwrap();
+        }
+        total
+    };
+    let total = tokio::time::timeout(std::time::Duration::from_secs(10), all)
+        .await
+        .expect("concurrent acquires deadlocked");
+    assert_eq!(total, 15);
+    for (key, uses) in pool.usage_stats().await {
+        assert_eq!(uses, 5, "{key} was used {uses} times");
+    }
+}
+
+#[tokio::test]
+async fn group_acquire_stays_within_its_tag() {
+    let pool = APIKeyPool::builder()
+        .with_key(APIKey::with_tag("maps-1", policy(), "maps"))
+        .with_key(APIKey::with_tag("search-1", policy(), "search"))
+        .with_key(APIKey::with_tag("maps-2", policy(), "maps"))
+        .with_key(APIKey::new("untagged", policy()))
+        .build();
+
+    for _ in 0..10 {
+        let key = pool.acquire_from_group("maps").await.unwrap();
+        assert!(key.starts_with("maps-"), "{key}");
+    }
+    for _ in 0..10 {
+        assert_eq!(
+            pool.acquire_from_group("search").await.as_deref(),
+            Some("search-1")
+        );
+    }
+    assert_eq!(pool.acquire_from_group("billing").await, None);
+}
+
+#[tokio::test]
+async fn try_acquire_n_takes_all_or_nothing() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, RateLimitPolicy::new(2, Duration::hours(1))))
+        .collect();
+
+    assert_eq!(pool.try_acquire_n(5).await, None);
+    assert_eq!(pool.available_capacity().await, 4);
+    assert_eq!(
+        pool.usage_stats().await,
+        [("a".to_string(), 0), ("b".to_string(), 0)]
+    );
+
+    let keys = pool.try_acquire_n(3).await.unwrap();
+    assert_eq!(keys, ["a", "a", "b"]);
+    assert_eq!(pool.available_capacity().await, 1);
+
+    // Asking for no uses takes none, even when none are left.
+    assert_eq!(pool.try_acquire_n(1).await.unwrap(), ["b"]);
+    assert_eq!(pool.try_acquire_n(0).await, Some(Vec::new()));
+}
+
+#[tokio::test]
+async fn acquire_indexed_reports_the_key_position() {
+    let pool: APIKeyPool = ["a", "b", "c"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+    let keys = pool.list_keys().await;
+    for _ in 0..6 {
+        let (index, key) = pool.acquire_indexed().await.unwrap();
+        assert_eq!(keys[index], key);
+    }
+    let (index, key) = pool.acquire_indexed().await.unwrap();
+    assert_eq!(pool.remove_at(index).await.unwrap().key(), key);
+}
+
+#[tokio::test]
+async fn health_check_blocks_unhealthy_keys() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+    let healthy = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&healthy);
+    pool.set_health_check(move |_| flag.load(Ordering::SeqCst))
+        .await;
+
+    assert_eq!(pool.poll_for_key().await, None);
+    healthy.store(true, Ordering::SeqCst);
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+}
+
+#[tokio::test]
+async fn acquire_with_meta_returns_the_key_with_its_label() {
+    let pool = APIKeyPool::builder()
+        .with_key(APIKey::with_meta(
+            "a",
+            RateLimitPolicy::new(1, Duration::minutes(1)),
+            serde_json::json!({ "label": "primary" }),
+        ))
+        .with_key(APIKey::new(
+            "b",
+            RateLimitPolicy::new(1, Duration::minutes(1)),
+        ))
+        .build();
+
+    let (key, meta) = pool.acquire_with_meta().await.unwrap();
+    assert_eq!(key, "a");
+    assert_eq!(meta.unwrap()["label"], "primary");
+    assert_eq!(
+        pool.acquire_with_meta().await,
+        Some(("b".to_string(), None))
+    );
+    assert_eq!(pool.acquire_with_meta().await, None);
+}
+
+#[tokio::test]
+async fn pressure_rises_until_the_pool_is_saturated() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(4, Duration::minutes(1)),
+    ))
+    .await;
+
+    let mut pressures = Vec::new();
+    for _ in 0..4 {
+        let (key, pressure) = pool.acquire_with_pressure().await;
+        assert_eq!(key.as_deref(), Some("a"));
+        pressures.push(pressure);
+    }
+    assert_eq!(pressures, [0.0, 0.25, 0.5, 0.75]);
+    assert_eq!(pool.acquire_with_pressure().await, (None, 1.0));
+}
+
+#[tokio::test]
+async fn key_failing_its_probe_is_skipped() {
+    let pool = APIKeyPool::new();
+    for key in ["a", "b", "c"] {
+        pool.add_key(APIKey::new(key, policy())).await;
+    }
+    let outcomes = pool
+        .probe_all(Duration::minutes(1), |key| async move { key != "b" })
+        .await;
+    assert_eq!(
+        outcomes,
+        [
+            ("a".to_string(), true),
+            ("b".to_string(), false),
+            ("c".to_string(), true)
+        ]
+    );
+
+    for _ in 0..10 {
+        let key = pool.poll_for_key().await.unwrap();
+        assert_ne!(key, "b");
+    }
+}
+
+#[tokio::test]
+async fn tasks_share_a_pool_through_its_arc() {
+    let pool = APIKeyPool::shared();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(2, Duration::minutes(1)),
+    ))
+    .await;
+
+    let tasks: Vec<_> = (0..2)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+            tokio::spawn(async move { pool.poll_for_key().await })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap().as_deref(), Some("a"));
+    }
+    assert_eq!(pool.key_info("a").await.unwrap().total_uses, 2);
+    assert_eq!(pool.poll_for_key().await, None);
+}
+
+#[tokio::test]
+async fn exhausted_key_is_unavailable_and_reaped() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::with_max_total_uses("a", policy(), 2))
+        .await;
+    pool.add_key(APIKey::new("b", policy())).await;
+    assert!(pool.reap_exhausted().await.is_empty());
+
+    let mut keys = Vec::new();
+    for _ in 0..4 {
+        keys.push(pool.poll_for_key().await.unwrap());
+    }
+    assert_eq!(keys, ["a", "b", "a", "b"]);
+    // The rate limit allows more, but the key's lifetime does not.
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("b"));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("b"));
+
+    let reaped = pool.reap_exhausted().await;
+    assert_eq!(reaped.len(), 1);
+    assert_eq!(reaped[0].key(), "a");
+    assert_eq!(pool.list_keys().await, ["b"]);
+}
+
+#[tokio::test]
+async fn min_capacity_skips_keys_with_too_few_uses_left() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "small",
+        RateLimitPolicy::new(5, Duration::minutes(1)),
+    ))
+    .await;
+    pool.add_key(APIKey::new(
+        "large",
+        RateLimitPolicy::new(50, Duration::minutes(1)),
+    ))
+    .await;
+
+    assert_eq!(
+        pool.acquire_with_min_capacity(10).await.as_deref(),
+        Some("large")
+    );
+    assert_eq!(
+        pool.acquire_with_min_capacity(49).await.as_deref(),
+        Some("large")
+    );
+    // The large key has 48 uses left now.
+    assert_eq!(pool.acquire_with_min_capacity(49).await, None);
+    assert_eq!(
+        pool.acquire_with_min_capacity(5).await.as_deref(),
+        Some("small")
+    );
+}
+
+#[tokio::test]
+async fn max_concurrent_blocks_the_key_while_a_guard_is_held() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::with_max_concurrent("a", policy(), 1))
+        .await;
+
+    let guard = pool.acquire_guard().await.unwrap();
+    assert_eq!(pool.poll_for_key().await, None);
+    assert!(pool.acquire_guard().await.is_none());
+
+    drop(guard);
+    assert!(pool.acquire_guard().await.is_some());
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn policy_reads_do_not_wait_on_an_acquire() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+    // The health check runs in the middle of the acquire, so blocking it holds the acquire there.
+    let barrier = Arc::new(Barrier::new(2));
+    let blocked = Arc::new(AtomicBool::new(true));
+    pool.set_health_check({
+        let barrier = Arc::clone(&barrier);
+        move |_| {
+            if blocked.swap(false, Ordering::SeqCst) {
+                barrier.wait();
+                barrier.wait();
+            }
+            true
+        }
+    })
+    .await;
+
+    let acquire = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.poll_for_key().await }
+    });
+    let entered = Arc::clone(&barrier);
+    tokio::task::spawn_blocking(move || entered.wait())
+        .await
+        .unwrap();
+    let read = tokio::time::timeout(std::time::Duration::from_secs(1), pool.policy("a")).await;
+    tokio::task::spawn_blocking(move || barrier.wait())
+        .await
+        .unwrap();
+
+    assert_eq!(read.unwrap().unwrap().count, 10);
+    assert_eq!(acquire.await.unwrap().as_deref(), Some("a"));
+}
+
+#[tokio::test]
+async fn acquire_any_of_stays_within_the_subset() {
+    let pool = APIKeyPool::new();
+    for key in ["a", "b", "c"] {
+        pool.add_key(APIKey::new(
+            key,
+            RateLimitPolicy::new(2, Duration::minutes(1)),
+        ))
+        .await;
+    }
+
+    let mut keys = Vec::new();
+    while let Some(key) = pool.acquire_any_of(&["b", "c"]).await {
+        keys.push(key);
+    }
+    keys.sort();
+    assert_eq!(keys, ["b", "b", "c", "c"]);
+    assert_eq!(pool.acquire_any_of(&["missing"]).await, None);
+    assert_eq!(pool.acquire_any_of(&["a"]).await.as_deref(), Some("a"));
+}
+
+#[tokio::test]
+async fn acquire_with_remaining_reports_the_uses_left() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(3, Duration::minutes(1)),
+    ))
+    .await;
+    assert_eq!(
+        pool.acquire_with_remaining().await,
+        Some(("a".to_string(), 2))
+    );
+    assert_eq!(
+        pool.acquire_with_remaining().await,
+        Some(("a".to_string(), 1))
+    );
+    assert_eq!(
+        pool.acquire_with_remaining().await,
+        Some(("a".to_string(), 0))
+    );
+    assert_eq!(pool.acquire_with_remaining().await, None);
+}
+
+#[tokio::test]
+async fn simulated_acquires_use_nothing() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(2, Duration::minutes(1)),
+    ))
+    .await;
+    pool.add_key(APIKey::new(
+        "b",
+        RateLimitPolicy::new(1, Duration::minutes(1)),
+    ))
+    .await;
+
+    let simulated = pool.simulate_acquires(4).await;
+    assert_eq!(
+        simulated,
+        [
+            Some("a".to_string()),
+            Some("b".to_string()),
+            Some("a".to_string()),
+            None
+        ]
+    );
+    assert_eq!(pool.available_capacity().await, 3);
+    assert_eq!(pool.key_info("a").await.unwrap().total_uses, 0);
+    assert_eq!(pool.key_info("b").await.unwrap().used_slots, 0);
+    // The real acquires follow the same order.
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("b"));
+}
+
+#[tokio::test]
+async fn scoped_work_records_exactly_one_use() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+
+    let result = pool
+        .scoped(|key| async move { Err::<(), _>(format!("{key} failed")) })
+        .await;
+    assert_eq!(result, Some(Err("a failed".to_string())));
+    let info = pool.key_info("a").await.unwrap();
+    assert_eq!((info.used_slots, info.total_uses), (1, 1));
+    assert_eq!(pool.in_flight("a").await, Some(0));
+
+    let panicked = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.scoped(|_| async { panic!("work failed") }).await }
+    })
+    .await;
+    assert!(panicked.is_err());
+    let info = pool.key_info("a").await.unwrap();
+    assert_eq!((info.used_slots, info.total_uses), (2, 2));
+    assert_eq!(pool.in_flight("a").await, Some(0));
+}
+
+#[tokio::test]
+async fn repeated_failures_double_the_cooldown() {
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let clock = MockClock::new(start);
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new("a", policy()))
+        .build();
+
+    for cooldown in [10, 20, 40] {
+        pool.report_failure("a", Duration::seconds(10)).await;
+        assert_eq!(
+            pool.next_available_at().await,
+            Some(clock.now() + Duration::seconds(cooldown))
+        );
+        clock.advance(Duration::seconds(cooldown));
+        assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+    }
+
+    // A success starts the cooldowns over.
+    pool.report_success("a").await;
+    pool.report_failure("a", Duration::seconds(10)).await;
+    assert_eq!(
+        pool.next_available_at().await,
+        Some(clock.now() + Duration::seconds(10))
+    );
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "reqwest")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::Duration;
+use reqwest::header::AUTHORIZATION;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use api_key_pool::*;
+
+/// Starts an HTTP server that rejects the API key `k1` with `429 Too Many Requests` and accepts any
+/// other, and returns its address along with the `Authorization` header of every request it saw.
+async fn serve() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let headers = Arc::clone(&seen);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            let auth = request
+                .lines()
+                .find_map(|line| line.strip_prefix("authorization: "))
+                .unwrap_or_default()
+                .to_string();
+            let response = if auth == "Bearer k1" {
+                "HTTP/1.1 429 Too Many Requests\r\nretry-after: 60\r\n"
+            } else {
+                "HTTP/1.1 200 OK\r\n"
+            };
+            headers.lock().unwrap().push(auth);
+            stream
+                .write_all(
+                    format!("{response}content-length: 0\r\nconnection: close\r\n\r\n").as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+    });
+    (address, seen)
+}
+
+#[tokio::test]
+async fn rate_limited_key_is_parked_and_the_request_retried() {
+    let (address, seen) = serve().await;
+    let pool = key_pool!(
+        policy = RateLimitPolicy::new(10, Duration::seconds(1));
+        "k1",
+        "k2",
+    );
+    let client =
+        PoolBackedClient::new(reqwest::Client::new(), pool, AUTHORIZATION).with_prefix("Bearer ");
+
+    for _ in 0..2 {
+        let request = client.client().get(&address).build().unwrap();
+        let response = client.send(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+    // The first request rotates to `k2` after `k1` is rejected, and the second skips the parked
+    // `k1` altogether.
+    assert_eq!(
+        *seen.lock().unwrap(),
+        ["Bearer k1", "Bearer k2", "Bearer k2"]
+    );
+    assert_eq!(client.pool().reliability("k1").await, Some(0.0));
+}
+
+/// A provider that hands out its API keys in turn and records every report.
+#[derive(Default)]
+struct MockProvider {
+    keys: Vec<String>,
+    next: AtomicUsize,
+    reports: Mutex<Vec<String>>,
+}
+
+impl KeyProvider for MockProvider {
+    fn try_acquire(&self) -> BoxFuture<'_, Result<String, PoolError>> {
+        let index = self.next.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move { Ok(self.keys[index % self.keys.len()].clone()) })
+    }
+
+    fn add_key(&self, _key: APIKey) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    fn len(&self) -> BoxFuture<'_, usize> {
+        Box::pin(async { self.keys.len() })
+    }
+
+    fn report_success<'a>(&'a self, key: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move { self.reports.lock().unwrap().push(format!("{key} ok")) })
+    }
+
+    fn report_failure<'a>(&'a self, key: &'a str, cooldown: Duration) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let secs = cooldown.num_seconds();
+            self.reports
+                .lock()
+                .unwrap()
+                .push(format!("{key} parked {secs}s"));
+        })
+    }
+}
+
+#[tokio::test]
+async fn client_draws_keys_from_any_provider() {
+    let (address, seen) = serve().await;
+    let provider = Arc::new(MockProvider {
+        keys: vec!["k1".to_string(), "k2".to_string()],
+        ..MockProvider::default()
+    });
+    let shared: Arc<dyn KeyProvider> = provider.clone();
+    let client =
+        PoolBackedClient::new(reqwest::Client::new(), shared, AUTHORIZATION).with_prefix("Bearer ");
+
+    let request = client.client().get(&address).build().unwrap();
+    assert_eq!(client.send(request).await.unwrap().status(), 200);
+    assert_eq!(*seen.lock().unwrap(), ["Bearer k1", "Bearer k2"]);
+    assert_eq!(
+        *provider.reports.lock().unwrap(),
+        ["k1 parked 60s", "k2 ok"]
+    );
+}
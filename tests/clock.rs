@@ -0,0 +1,56 @@
+use chrono::{Duration, TimeZone, Utc};
+use tokio::time::Instant;
+
+use api_key_pool::*;
+
+#[tokio::test]
+async fn mock_clock_drives_readiness() {
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new(
+            "a",
+            RateLimitPolicy::new(1, Duration::seconds(10)),
+        ))
+        .build();
+
+    assert_eq!(pool.try_acquire().await, Ok("a".to_string()));
+    assert_eq!(
+        pool.try_acquire().await,
+        Err(PoolError::AllBusy {
+            retry_after: Duration::seconds(10)
+        })
+    );
+
+    clock.advance(Duration::seconds(9));
+    assert_eq!(
+        pool.try_acquire().await,
+        Err(PoolError::AllBusy {
+            retry_after: Duration::seconds(1)
+        })
+    );
+
+    clock.advance(Duration::seconds(1));
+    assert_eq!(pool.try_acquire().await, Ok("a".to_string()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn tokio_clock_waits_in_virtual_time() {
+    let pool = APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_key(APIKey::new(
+            "a",
+            RateLimitPolicy::new(1, Duration::seconds(2)),
+        ))
+        .build();
+    pool.try_acquire().await.unwrap();
+
+    let start = Instant::now();
+    assert_eq!(pool.wait_for_key().await, Some("a".to_string()));
+    let waited = start.elapsed();
+    assert!(waited >= std::time::Duration::from_secs(2), "{waited:?}");
+    assert!(
+        waited < std::time::Duration::from_millis(2010),
+        "{waited:?}"
+    );
+}
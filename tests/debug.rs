@@ -0,0 +1,28 @@
+use chrono::Duration;
+
+use api_key_pool::*;
+
+const SECRET: &str = "sk-live-0123456789abcdef";
+
+#[tokio::test]
+async fn debug_output_masks_the_key() {
+    let key = APIKey::new(SECRET, RateLimitPolicy::new(1, Duration::seconds(1)));
+    let formatted = format!("{key:?}");
+    assert!(!formatted.contains(SECRET), "{formatted}");
+    assert!(formatted.contains("…cdef"), "{formatted}");
+    assert!(formatted.contains("len: 24"), "{formatted}");
+
+    let pool = APIKeyPool::new();
+    pool.add_key(key).await;
+    let formatted = format!("{pool:?}");
+    assert!(!formatted.contains(SECRET), "{formatted}");
+    assert!(formatted.contains("…cdef"), "{formatted}");
+
+    let guard = pool.acquire_guard().await.unwrap();
+    let formatted = format!("{guard:?}");
+    assert!(!formatted.contains(SECRET), "{formatted}");
+    assert!(formatted.contains("…cdef"), "{formatted}");
+
+    assert_eq!(pool.list_keys_masked().await, ["…cdef"]);
+    assert_eq!(pool.key_info(SECRET).await.unwrap().key, "…cdef");
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "metrics")]
+
+use chrono::Duration;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+use api_key_pool::*;
+
+/// Returns the name and value of every metric recorded since the last snapshot, sorted by name.
+fn snapshot(recorder: &DebuggingRecorder) -> Vec<(String, DebugValue)> {
+    let mut metrics: Vec<_> = recorder
+        .snapshotter()
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+        .collect();
+    metrics.sort_by(|a, b| a.0.cmp(&b.0));
+    metrics
+}
+
+/// Returns how many times API keys were checked for readiness since the last snapshot.
+fn readiness_checks(recorder: &DebuggingRecorder) -> u64 {
+    snapshot(recorder)
+        .into_iter()
+        .find_map(|(name, value)| match value {
+            DebugValue::Counter(count) if name == "api_key_pool_readiness_checks_total" => {
+                Some(count)
+            }
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+#[test]
+fn acquires_are_counted() {
+    let recorder = DebuggingRecorder::new();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    metrics::with_local_recorder(&recorder, || {
+        runtime.block_on(async {
+            let pool = APIKeyPool::new();
+            pool.add_key(APIKey::new(
+                "a",
+                RateLimitPolicy::new(5, Duration::minutes(1)),
+            ))
+            .await;
+            pool.poll_for_key().await.unwrap();
+            pool.poll_for_key().await.unwrap();
+            let metrics: Vec<_> = snapshot(&recorder)
+                .into_iter()
+                .filter(|(name, _)| name != "api_key_pool_readiness_checks_total")
+                .collect();
+            assert_eq!(
+                metrics,
+                [(
+                    "api_key_pool_acquires_total".to_string(),
+                    DebugValue::Counter(2)
+                )]
+            );
+
+            // Every use taken at once counts, and so does a reservation.
+            pool.try_acquire_n(2).await.unwrap();
+            pool.reserve_at(chrono::Utc::now()).await.unwrap();
+            assert!(snapshot(&recorder).contains(&(
+                "api_key_pool_acquires_total".to_string(),
+                DebugValue::Counter(3)
+            )));
+        });
+    });
+}
+
+#[test]
+fn acquire_checks_few_of_a_thousand_keys() {
+    let recorder = DebuggingRecorder::new();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    metrics::with_local_recorder(&recorder, || {
+        runtime.block_on(async {
+            // Only the last few API keys stay ready once each has been used.
+            let pool: APIKeyPool = (0..1000)
+                .map(|i| {
+                    let count = if i < 990 { 1 } else { 1000 };
+                    APIKey::new(
+                        &format!("key-{i}"),
+                        RateLimitPolicy::new(count, Duration::hours(1)),
+                    )
+                })
+                .collect();
+            for _ in 0..1000 {
+                pool.poll_for_key().await.unwrap();
+            }
+
+            // Each acquire starts its scan from the first API key, but only the ready ones at the
+            // end of the pool are checked.
+            for _ in 0..20 {
+                readiness_checks(&recorder);
+                let key = pool.poll_for_key().await.unwrap();
+                assert!(key.as_str() >= "key-990");
+                assert!(readiness_checks(&recorder) < 10);
+            }
+
+            // Once every API key is busy, the index answers after checking only a few of them.
+            let pool: APIKeyPool = (0..1000)
+                .map(|i| {
+                    APIKey::new(
+                        &format!("key-{i}"),
+                        RateLimitPolicy::new(1, Duration::hours(1)),
+                    )
+                })
+                .collect();
+            for _ in 0..1000 {
+                pool.poll_for_key().await.unwrap();
+            }
+            readiness_checks(&recorder);
+            assert!(matches!(
+                pool.try_acquire().await,
+                Err(PoolError::AllBusy { .. })
+            ));
+            assert!(readiness_checks(&recorder) < 10);
+        });
+    });
+}
@@ -0,0 +1,443 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use api_key_pool::*;
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+}
+
+/// Returns a pool on a mock clock holding a single API key, along with the clock.
+fn mock_pool(policy: RateLimitPolicy) -> (APIKeyPool, MockClock) {
+    let clock = MockClock::new(start());
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new("a", policy))
+        .build();
+    (pool, clock)
+}
+
+#[tokio::test]
+async fn every_slot_frees_once_the_window_passes() {
+    let (pool, clock) = mock_pool(RateLimitPolicy::new(5, Duration::minutes(1)));
+    for _ in 0..5 {
+        assert!(pool.poll_for_key().await.is_some());
+    }
+    assert_eq!(pool.poll_for_key().await, None);
+    assert_eq!(pool.available_capacity().await, 0);
+
+    clock.advance(Duration::minutes(1));
+    assert_eq!(pool.available_capacity().await, 5);
+    for _ in 0..5 {
+        assert!(pool.poll_for_key().await.is_some());
+    }
+}
+
+#[tokio::test]
+async fn time_until_ready_counts_down_the_window() {
+    let (pool, clock) = mock_pool(RateLimitPolicy::new(1, Duration::seconds(10)));
+    pool.poll_for_key().await.unwrap();
+    let info = pool.key_info("a").await.unwrap();
+    assert_eq!(info.time_until_ready, Duration::seconds(10));
+
+    clock.advance(Duration::seconds(3));
+    let key = pool.drain().await.pop().unwrap();
+    assert_eq!(key.time_until_ready().await, Duration::seconds(7));
+
+    clock.advance(Duration::seconds(7));
+    assert_eq!(key.time_until_ready().await, Duration::zero());
+}
+
+#[tokio::test]
+async fn token_bucket_allows_one_use_per_refill_interval() {
+    let (pool, clock) = mock_pool(RateLimitPolicy::token_bucket(10, Duration::seconds(10), 1));
+    for _ in 0..10 {
+        assert!(pool.poll_for_key().await.is_some());
+        assert_eq!(
+            pool.try_acquire().await,
+            Err(PoolError::AllBusy {
+                retry_after: Duration::seconds(1)
+            })
+        );
+        clock.advance(Duration::seconds(1));
+    }
+}
+
+#[test]
+fn token_bucket_rejects_a_zero_burst() {
+    assert_eq!(
+        RateLimitPolicy::try_token_bucket(1, Duration::seconds(1), 0).unwrap_err(),
+        PolicyError::ZeroBurst
+    );
+    assert_eq!(
+        RateLimitPolicy::try_token_bucket(0, Duration::seconds(1), 1).unwrap_err(),
+        PolicyError::ZeroCount
+    );
+    assert!(RateLimitPolicy::try_token_bucket(1, Duration::seconds(1), 1).is_ok());
+}
+
+#[tokio::test]
+async fn leaky_bucket_lets_its_capacity_through_back_to_back() {
+    assert_eq!(
+        RateLimitPolicy::try_leaky_bucket(1, Duration::seconds(1), 0).unwrap_err(),
+        PolicyError::ZeroCapacity
+    );
+
+    // One use drains every second, and the bucket holds three.
+    let (pool, clock) = mock_pool(RateLimitPolicy::leaky_bucket(1, Duration::seconds(1), 3));
+    for _ in 0..3 {
+        assert!(pool.poll_for_key().await.is_some());
+    }
+    assert_eq!(
+        pool.try_acquire().await,
+        Err(PoolError::AllBusy {
+            retry_after: Duration::seconds(1)
+        })
+    );
+
+    // Once the bucket is full, uses drain one at a time.
+    clock.advance(Duration::seconds(1));
+    assert!(pool.poll_for_key().await.is_some());
+    assert_eq!(pool.poll_for_key().await, None);
+    clock.advance(Duration::milliseconds(500));
+    assert_eq!(pool.poll_for_key().await, None);
+    clock.advance(Duration::milliseconds(500));
+    assert!(pool.poll_for_key().await.is_some());
+
+    // An idle bucket drains completely.
+    clock.advance(Duration::seconds(3));
+    assert_eq!(pool.available_capacity().await, 3);
+}
+
+#[tokio::test]
+async fn tightened_policy_applies_right_away() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy::new(10, Duration::minutes(1)));
+    for _ in 0..3 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert_eq!(pool.available_capacity().await, 7);
+
+    // Three of ten slots used keeps a third of the window used: one of three slots.
+    assert!(
+        pool.update_policy("a", RateLimitPolicy::new(3, Duration::minutes(1)))
+            .await
+    );
+    assert_eq!(pool.available_capacity().await, 2);
+    for _ in 0..2 {
+        assert!(pool.poll_for_key().await.is_some());
+    }
+    assert_eq!(pool.poll_for_key().await, None);
+}
+
+#[tokio::test]
+async fn global_policy_throttles_every_key() {
+    let clock = MockClock::new(start());
+    let unlimited = RateLimitPolicy::new(1000, Duration::seconds(1));
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_global_policy(RateLimitPolicy::new(1, Duration::seconds(1)))
+        .with_key(APIKey::new("a", unlimited))
+        .with_key(APIKey::new("b", unlimited))
+        .build();
+
+    for _ in 0..3 {
+        assert!(pool.poll_for_key().await.is_some());
+        assert_eq!(
+            pool.try_acquire().await,
+            Err(PoolError::AllBusy {
+                retry_after: Duration::seconds(1)
+            })
+        );
+        assert_eq!(pool.available_capacity().await, 0);
+        clock.advance(Duration::seconds(1));
+    }
+}
+
+#[tokio::test]
+async fn key_info_reports_used_slots() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy::new(2, Duration::seconds(10)));
+    pool.poll_for_key().await.unwrap();
+
+    let info = pool.key_info("a").await.unwrap();
+    assert_eq!(info.count, 2);
+    assert_eq!(info.per, Duration::seconds(10));
+    assert_eq!(info.used_slots, 1);
+    assert_eq!(info.time_until_ready, Duration::zero());
+    assert_eq!(info.next_available, Some(start()));
+    assert_eq!(info.total_uses, 1);
+    assert_eq!(pool.key_info("missing").await, None);
+}
+
+#[tokio::test]
+async fn key_with_full_history_starts_busy() {
+    let clock = MockClock::new(start());
+    let policy = RateLimitPolicy::new(2, Duration::minutes(1));
+    let history = vec![
+        start() - Duration::seconds(30),
+        start() - Duration::seconds(10),
+    ];
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::with_history("a", policy, history))
+        .build();
+
+    assert_eq!(
+        pool.try_acquire().await,
+        Err(PoolError::AllBusy {
+            retry_after: Duration::seconds(30)
+        })
+    );
+    clock.advance(Duration::seconds(30));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+    assert_eq!(pool.poll_for_key().await, None);
+}
+
+#[test]
+fn shorthand_policies_parse() {
+    for (shorthand, count, per) in [
+        ("100/60s", 100, Duration::seconds(60)),
+        ("5/1m", 5, Duration::minutes(1)),
+        ("1000/1h", 1000, Duration::hours(1)),
+        ("10/s", 10, Duration::seconds(1)),
+        ("1/500ms", 1, Duration::milliseconds(500)),
+        (" 3 / 2d ", 3, Duration::days(2)),
+    ] {
+        let policy: RateLimitPolicy = shorthand.parse().unwrap();
+        assert_eq!((policy.count, policy.per), (count, per), "{shorthand}");
+        assert_eq!(policy.algorithm, RateLimitAlgorithm::SlidingWindow);
+    }
+}
+
+#[test]
+fn malformed_shorthand_is_an_error() {
+    for (shorthand, error) in [
+        ("100", ParsePolicyError::MissingSeparator),
+        ("x/1s", ParsePolicyError::InvalidCount),
+        ("0/1s", ParsePolicyError::InvalidCount),
+        ("-1/1s", ParsePolicyError::InvalidCount),
+        ("1/0s", ParsePolicyError::InvalidDuration),
+        ("1/99999999999999999999s", ParsePolicyError::InvalidDuration),
+        ("1/9999999999999999d", ParsePolicyError::InvalidDuration),
+        ("1/10", ParsePolicyError::UnknownUnit),
+        ("1/10w", ParsePolicyError::UnknownUnit),
+        ("1/", ParsePolicyError::UnknownUnit),
+    ] {
+        assert_eq!(
+            shorthand.parse::<RateLimitPolicy>().unwrap_err(),
+            error,
+            "{shorthand}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn drained_history_restores_an_equivalent_key() {
+    let policy = RateLimitPolicy::new(3, Duration::minutes(1));
+    let (pool, clock) = mock_pool(policy);
+    pool.poll_for_key().await.unwrap();
+    clock.advance(Duration::seconds(10));
+    pool.poll_for_key().await.unwrap();
+
+    let drained = pool.drain().await;
+    assert!(pool.is_empty().await);
+    let uses = drained[0].recent_uses().await;
+    assert_eq!(uses, [start(), start() + Duration::seconds(10)]);
+
+    let restored = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::with_history(drained[0].key(), policy, uses))
+        .build();
+    let info = restored.key_info("a").await.unwrap();
+    assert_eq!(info.used_slots, 2);
+    assert_eq!(restored.available_capacity().await, 1);
+    clock.advance(Duration::seconds(50));
+    assert_eq!(restored.available_capacity().await, 2);
+}
+
+#[test]
+fn try_new_rejects_invalid_parameters() {
+    assert_eq!(
+        RateLimitPolicy::try_new(0, Duration::seconds(1)).unwrap_err(),
+        PolicyError::ZeroCount
+    );
+    assert_eq!(
+        RateLimitPolicy::try_new(1, Duration::zero()).unwrap_err(),
+        PolicyError::NonPositivePer
+    );
+    assert_eq!(
+        RateLimitPolicy::try_new(1, Duration::seconds(-5)).unwrap_err(),
+        PolicyError::NonPositivePer
+    );
+    assert!(RateLimitPolicy::try_new(1, Duration::seconds(1)).is_ok());
+}
+
+#[test]
+#[should_panic(expected = "`count` must not be zero")]
+fn new_panics_on_a_zero_count() {
+    RateLimitPolicy::new(0, Duration::seconds(1));
+}
+
+#[tokio::test]
+async fn updated_burst_policy_keeps_the_hourly_limit() {
+    let clock = MockClock::new(start());
+    let burst = RateLimitPolicy::new(2, Duration::seconds(1));
+    let hourly = RateLimitPolicy::new(5, Duration::hours(1));
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::with_policies("a", vec![burst, hourly]))
+        .build();
+    for _ in 0..2 {
+        pool.poll_for_key().await.unwrap();
+    }
+    clock.advance(Duration::seconds(1));
+
+    // The wider burst passes, but the hourly limit still counts the two earlier uses.
+    assert!(
+        pool.update_policy("a", RateLimitPolicy::new(10, Duration::seconds(1)))
+            .await
+    );
+    for _ in 0..3 {
+        assert!(pool.poll_for_key().await.is_some());
+    }
+    assert_eq!(pool.poll_for_key().await, None);
+
+    // Replacing every policy drops the hourly limit.
+    assert!(
+        pool.update_policies("a", vec![RateLimitPolicy::new(10, Duration::seconds(1))])
+            .await
+    );
+    assert!(pool.poll_for_key().await.is_some());
+    assert!(!pool.update_policies("b", vec![hourly]).await);
+}
+
+#[tokio::test]
+async fn resized_window_permits_no_burst() {
+    for (per, wait) in [(10, 5), (120, 60)] {
+        let (pool, clock) = mock_pool(RateLimitPolicy::new(10, Duration::minutes(1)));
+        for _ in 0..10 {
+            pool.poll_for_key().await.unwrap();
+        }
+        clock.advance(Duration::seconds(30));
+
+        // Half of the old window has passed, so half of the new window is left to wait out.
+        pool.update_policy("a", RateLimitPolicy::new(10, Duration::seconds(per)))
+            .await;
+        assert_eq!(pool.poll_for_key().await, None);
+        assert_eq!(
+            pool.next_available_at().await,
+            Some(start() + Duration::seconds(30 + wait))
+        );
+        clock.advance(Duration::seconds(wait));
+        assert_eq!(pool.available_capacity().await, 10);
+    }
+}
+
+#[tokio::test]
+async fn reservations_count_right_away() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy::new(2, Duration::minutes(1)));
+    let later = start() + Duration::seconds(30);
+    assert_eq!(pool.reserve_at(later).await.as_deref(), Some("a"));
+    assert_eq!(pool.key_info("a").await.unwrap().used_slots, 1);
+    assert_eq!(pool.available_capacity().await, 1);
+    assert_eq!(pool.reserve_at(later).await.as_deref(), Some("a"));
+    assert_eq!(pool.reserve_at(later).await, None);
+    assert_eq!(pool.poll_for_key().await, None);
+}
+
+#[tokio::test]
+async fn shut_down_pool_takes_no_reservations() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy::new(2, Duration::minutes(1)));
+    pool.shutdown();
+    assert_eq!(pool.reserve_at(start()).await, None);
+    assert_eq!(pool.key_info("a").await.unwrap().total_uses, 0);
+}
+
+#[tokio::test]
+async fn reservation_in_the_next_fixed_window_leaves_the_current_one() {
+    let (pool, clock) = mock_pool(RateLimitPolicy::fixed_window(2, Duration::minutes(1)));
+    let next = start() + Duration::seconds(90);
+    for _ in 0..2 {
+        assert_eq!(pool.reserve_at(next).await.as_deref(), Some("a"));
+    }
+    assert_eq!(pool.reserve_at(next).await, None);
+
+    // The current window is untouched, and the next one is booked up.
+    assert_eq!(pool.available_capacity().await, 2);
+    for _ in 0..2 {
+        assert!(pool.poll_for_key().await.is_some());
+    }
+    clock.advance(Duration::minutes(1));
+    assert_eq!(pool.available_capacity().await, 0);
+    assert_eq!(pool.key_info("a").await.unwrap().used_slots, 2);
+    clock.advance(Duration::minutes(1));
+    assert_eq!(pool.available_capacity().await, 2);
+}
+
+#[tokio::test]
+async fn tightened_policy_keeps_no_more_uses_than_its_count() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy::new(100, Duration::minutes(1)));
+    for _ in 0..80 {
+        pool.poll_for_key().await.unwrap();
+    }
+    pool.update_policy("a", RateLimitPolicy::new(5, Duration::minutes(1)))
+        .await;
+    pool.shrink_to_fit().await;
+
+    // Eighty of a hundred slots used keeps four of five.
+    assert_eq!(pool.key_info("a").await.unwrap().used_slots, 4);
+    let drained = pool.drain().await;
+    assert_eq!(drained[0].recent_uses().await.len(), 4);
+}
+
+#[tokio::test]
+async fn policy_with_a_zero_count_can_be_replaced() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy {
+        count: 0,
+        per: Duration::minutes(1),
+        algorithm: RateLimitAlgorithm::SlidingWindow,
+    });
+    assert_eq!(pool.poll_for_key().await, None);
+    pool.update_policy("a", RateLimitPolicy::new(3, Duration::minutes(1)))
+        .await;
+    assert_eq!(pool.available_capacity().await, 3);
+}
+
+#[tokio::test]
+async fn small_and_large_counts_share_readiness() {
+    // Counts of at most eight keep their uses inline, and larger ones in a heap.
+    for count in [1, 3, 8, 9, 20] {
+        let per = Duration::seconds(100);
+        let (pool, clock) = mock_pool(RateLimitPolicy::new(count, per));
+        for _ in 0..count {
+            assert!(pool.poll_for_key().await.is_some(), "count {count}");
+            clock.advance(Duration::seconds(1));
+        }
+        assert_eq!(pool.poll_for_key().await, None, "count {count}");
+        assert_eq!(pool.next_available_at().await, Some(start() + per));
+
+        // The uses leave the window oldest first, one each second.
+        clock.set(start() + per);
+        assert_eq!(pool.available_capacity().await, 1, "count {count}");
+        clock.set(start() + per + Duration::seconds(count as i64 - 1));
+        assert_eq!(pool.available_capacity().await, count, "count {count}");
+    }
+}
+
+#[tokio::test]
+async fn uses_survive_moving_between_inline_and_heap_storage() {
+    let (pool, _clock) = mock_pool(RateLimitPolicy::new(4, Duration::minutes(1)));
+    for _ in 0..4 {
+        pool.poll_for_key().await.unwrap();
+    }
+    pool.update_policy("a", RateLimitPolicy::new(20, Duration::minutes(1)))
+        .await;
+    assert_eq!(pool.key_info("a").await.unwrap().used_slots, 4);
+    for _ in 0..16 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert_eq!(pool.poll_for_key().await, None);
+
+    pool.update_policy("a", RateLimitPolicy::new(4, Duration::minutes(1)))
+        .await;
+    assert_eq!(pool.key_info("a").await.unwrap().used_slots, 4);
+    assert_eq!(pool.poll_for_key().await, None);
+}
@@ -0,0 +1,572 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, TimeZone, Utc};
+
+use api_key_pool::*;
+
+fn policy() -> RateLimitPolicy {
+    RateLimitPolicy::new(10, Duration::seconds(1))
+}
+
+#[tokio::test]
+async fn removed_key_is_never_handed_out() {
+    let pool = APIKeyPool::new();
+    for key in ["a", "b", "c"] {
+        pool.add_key(APIKey::new(key, policy())).await;
+    }
+    assert!(pool.remove_key("b").await);
+    assert!(!pool.remove_key("b").await);
+
+    for _ in 0..20 {
+        let key = pool.poll_for_key().await;
+        assert_ne!(key.as_deref(), Some("b"));
+    }
+}
+
+#[tokio::test]
+async fn len_tracks_added_keys() {
+    let pool = APIKeyPool::new();
+    assert!(pool.is_empty().await);
+    assert_eq!(pool.len().await, 0);
+
+    pool.add_key(APIKey::new("a", policy())).await;
+    assert!(!pool.is_empty().await);
+    assert_eq!(pool.len().await, 1);
+
+    pool.add_key(APIKey::new("b", policy())).await;
+    pool.add_key(APIKey::new("c", policy())).await;
+    assert_eq!(pool.len().await, 3);
+}
+
+#[tokio::test]
+async fn builder_holds_every_key() {
+    let pool = APIKeyPool::builder()
+        .with_key(APIKey::new("a", policy()))
+        .with_key(APIKey::new("b", policy()))
+        .with_keys(["c", "d", "e"].map(|key| APIKey::new(key, policy())))
+        .build();
+    assert_eq!(pool.len().await, 5);
+    assert_eq!(pool.list_keys().await, ["a", "b", "c", "d", "e"]);
+}
+
+#[tokio::test]
+async fn from_env_adds_prefixed_keys_in_name_order() {
+    std::env::set_var("API_KEY_POOL_TEST_KEY_2", "second");
+    std::env::set_var("API_KEY_POOL_TEST_KEY_1", "first");
+    std::env::set_var("API_KEY_POOL_TEST_KEY_3", "third");
+    std::env::set_var("API_KEY_POOL_TEST_OTHER", "ignored");
+
+    let pool = APIKeyPool::from_env("API_KEY_POOL_TEST_KEY_", policy());
+    assert_eq!(pool.list_keys().await, ["first", "second", "third"]);
+}
+
+#[tokio::test]
+async fn clear_empties_the_pool() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+    pool.poll_for_key().await.unwrap();
+
+    pool.clear().await;
+    assert!(pool.is_empty().await);
+    assert_eq!(pool.poll_for_key().await, None);
+    assert_eq!(pool.try_acquire().await, Err(PoolError::Empty));
+}
+
+#[tokio::test]
+async fn usage_stats_count_every_acquire() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(100, Duration::seconds(1)),
+    ))
+    .await;
+    for _ in 0..7 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert_eq!(pool.usage_stats().await, [("a".to_string(), 7)]);
+    assert_eq!(pool.key_info("a").await.unwrap().total_uses, 7);
+}
+
+#[tokio::test]
+async fn clones_share_usage() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(1, Duration::seconds(10)),
+    ))
+    .await;
+    let clone = pool.clone();
+
+    assert_eq!(clone.poll_for_key().await.as_deref(), Some("a"));
+    assert_eq!(pool.poll_for_key().await, None);
+    assert_eq!(pool.usage_stats().await, [("a".to_string(), 1)]);
+
+    clone.add_key(APIKey::new("b", policy())).await;
+    assert_eq!(pool.len().await, 2);
+}
+
+#[tokio::test]
+async fn available_capacity_sums_the_free_slots() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, RateLimitPolicy::new(3, Duration::hours(1))))
+        .collect();
+    assert_eq!(pool.available_capacity().await, 6);
+
+    pool.poll_for_key().await.unwrap();
+    pool.poll_for_key().await.unwrap();
+    assert_eq!(pool.available_capacity().await, 4);
+}
+
+#[tokio::test]
+async fn acquire_callback_sees_every_key_in_order() {
+    let pool: APIKeyPool = ["a", "b", "c"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let record = std::sync::Arc::clone(&seen);
+    pool.on_acquire(move |key, _| record.lock().unwrap().push(key.to_string()));
+
+    let mut acquired = Vec::new();
+    for _ in 0..5 {
+        acquired.push(pool.poll_for_key().await.unwrap());
+    }
+    assert_eq!(*seen.lock().unwrap(), acquired);
+    assert_eq!(acquired, ["a", "b", "c", "a", "b"]);
+}
+
+#[tokio::test]
+async fn pool_collects_from_keys() {
+    let pool: APIKeyPool = ["a", "b", "c"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+    assert_eq!(pool.len().await, 3);
+    assert_eq!(pool.list_keys().await, ["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn add_key_unique_rejects_duplicates() {
+    let pool = APIKeyPool::new();
+    assert!(!pool.contains("a").await);
+    assert!(pool.add_key_unique(APIKey::new("a", policy())).await);
+    assert!(pool.contains("a").await);
+    assert!(!pool.add_key_unique(APIKey::new("a", policy())).await);
+    assert_eq!(pool.len().await, 1);
+}
+
+#[tokio::test]
+async fn saturation_is_the_used_share_of_capacity() {
+    let pool: APIKeyPool = ["a", "b"]
+        .into_iter()
+        .map(|key| APIKey::new(key, RateLimitPolicy::new(4, Duration::hours(1))))
+        .collect();
+    assert_eq!(pool.saturation().await, 0.0);
+
+    for _ in 0..3 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert!((pool.saturation().await - 0.375).abs() < 1e-9);
+
+    pool.set_enabled("b", false).await;
+    assert!((pool.saturation().await - 0.5).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn idle_keys_lists_keys_unused_past_the_threshold() {
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_keys(["busy", "idle"].map(|key| APIKey::new(key, policy())))
+        .build();
+    pool.acquire_any_of(&["idle"]).await.unwrap();
+    assert_eq!(pool.idle_keys(Duration::hours(1)).await, ["busy"]);
+
+    clock.advance(Duration::minutes(30));
+    pool.acquire_any_of(&["busy"]).await.unwrap();
+    clock.advance(Duration::minutes(31));
+    assert_eq!(pool.idle_keys(Duration::hours(1)).await, ["idle"]);
+}
+
+#[tokio::test]
+async fn list_keys_keeps_insertion_order() {
+    let pool = APIKeyPool::new();
+    for key in ["sk-zulu-00000001", "sk-alpha-0000002", "sk-mike-00000003"] {
+        pool.add_key(APIKey::new(key, policy())).await;
+    }
+    assert_eq!(
+        pool.list_keys().await,
+        ["sk-zulu-00000001", "sk-alpha-0000002", "sk-mike-00000003"]
+    );
+    assert_eq!(pool.list_keys_masked().await, ["…0001", "…0002", "…0003"]);
+}
+
+#[tokio::test]
+async fn merge_moves_keys_and_rejects_duplicates() {
+    let one = RateLimitPolicy::new(1, Duration::hours(1));
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", one)).await;
+    let other = APIKeyPool::new();
+    other.add_key(APIKey::new("b", one)).await;
+    other.add_key(APIKey::new("c", one)).await;
+    other.poll_for_key().await.unwrap();
+
+    pool.merge(other.clone()).await;
+    assert_eq!(pool.list_keys().await, ["a", "b", "c"]);
+    assert!(other.is_empty().await);
+    // Each API key keeps a usage history of its own, including uses from before the merge.
+    assert_eq!(pool.key_info("b").await.unwrap().used_slots, 1);
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("c"));
+    assert_eq!(pool.poll_for_key().await, None);
+
+    let duplicate = APIKeyPool::new();
+    duplicate.add_key(APIKey::new("a", one)).await;
+    pool.merge(duplicate.clone()).await;
+    assert_eq!(pool.len().await, 3);
+    assert_eq!(duplicate.list_keys().await, ["a"]);
+}
+
+#[tokio::test]
+async fn next_available_at_is_the_sooner_key() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let clock = MockClock::new(now);
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new(
+            "a",
+            RateLimitPolicy::new(1, Duration::seconds(30)),
+        ))
+        .with_key(APIKey::new(
+            "b",
+            RateLimitPolicy::new(1, Duration::seconds(10)),
+        ))
+        .build();
+    assert_eq!(pool.next_available_at().await, Some(now));
+
+    pool.poll_for_key().await.unwrap();
+    assert_eq!(pool.next_available_at().await, Some(now));
+    pool.poll_for_key().await.unwrap();
+    assert_eq!(
+        pool.next_available_at().await,
+        Some(now + Duration::seconds(10))
+    );
+
+    // Disabled API keys still have a time, so only an empty pool has none.
+    pool.set_enabled("a", false).await;
+    pool.set_enabled("b", false).await;
+    assert_eq!(
+        pool.next_available_at().await,
+        Some(now + Duration::seconds(10))
+    );
+    clock.advance(Duration::seconds(20));
+    assert_eq!(
+        pool.next_available_at().await,
+        Some(now + Duration::seconds(20))
+    );
+    assert_eq!(APIKeyPool::new().next_available_at().await, None);
+}
+
+#[tokio::test]
+async fn key_pool_macro_builds_every_key_with_its_policy() {
+    let pool = key_pool!(
+        policy = RateLimitPolicy::new(5, Duration::seconds(1));
+        "k1",
+        "k2",
+        "k3" => RateLimitPolicy::new(10, Duration::seconds(1)),
+    );
+    assert_eq!(pool.len().await, 3);
+    assert_eq!(pool.list_keys().await, ["k1", "k2", "k3"]);
+    for (key, count) in [("k1", 5), ("k2", 5), ("k3", 10)] {
+        let policy = pool.policy(key).await.unwrap();
+        assert_eq!((policy.count, policy.per), (count, Duration::seconds(1)));
+    }
+}
+
+#[tokio::test]
+async fn display_and_summary_have_the_expected_shapes() {
+    assert_eq!(
+        RateLimitPolicy::new(5, Duration::seconds(2)).to_string(),
+        "5 / 2s"
+    );
+    assert_eq!(
+        RateLimitPolicy::new(100, Duration::minutes(1)).to_string(),
+        "100 / 1m"
+    );
+    assert_eq!(
+        RateLimitPolicy::new(10, Duration::milliseconds(1500)).to_string(),
+        "10 / 1500ms"
+    );
+    assert_eq!(
+        RateLimitPolicy::token_bucket(10, Duration::seconds(1), 20).to_string(),
+        "10 / 1s (token bucket, burst 20)"
+    );
+
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new(
+            "a",
+            RateLimitPolicy::new(4, Duration::seconds(1)),
+        ))
+        .with_key(APIKey::new(
+            "b",
+            RateLimitPolicy::new(4, Duration::seconds(1)),
+        ))
+        .with_key(APIKey::new(
+            "c",
+            RateLimitPolicy::new(4, Duration::seconds(1)),
+        ))
+        .build();
+    for _ in 0..5 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert_eq!(pool.summary().await, "APIKeyPool(3 keys, 7/12 slots free)");
+
+    // Uses that have aged out of their window count as free.
+    clock.advance(Duration::seconds(1));
+    assert_eq!(pool.summary().await, "APIKeyPool(3 keys, 12/12 slots free)");
+}
+
+#[tokio::test]
+async fn snapshot_describes_every_key() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let clock = MockClock::new(now);
+    let policy = RateLimitPolicy::new(2, Duration::seconds(10));
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new("sk-aaaa1111", policy))
+        .with_key(APIKey::new("sk-bbbb2222bbbb2222", policy))
+        .build();
+    for _ in 0..3 {
+        pool.poll_for_key().await.unwrap();
+    }
+
+    let snapshot = pool.snapshot().await;
+    drop(pool);
+    clock.advance(Duration::seconds(10));
+    assert_eq!(
+        snapshot.keys,
+        [
+            KeyInfo {
+                key: "…11".to_string(),
+                count: 2,
+                per: Duration::seconds(10),
+                used_slots: 2,
+                time_until_ready: Duration::seconds(10),
+                next_available: Some(now + Duration::seconds(10)),
+                total_uses: 2,
+            },
+            KeyInfo {
+                key: "…2222".to_string(),
+                count: 2,
+                per: Duration::seconds(10),
+                used_slots: 1,
+                time_until_ready: Duration::zero(),
+                next_available: Some(now),
+                total_uses: 1,
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn removing_the_first_key_keeps_the_rotation() {
+    let pool = APIKeyPool::new();
+    for key in ["a", "b", "c"] {
+        pool.add_key(APIKey::new(key, policy())).await;
+    }
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+    assert!(pool.remove_key("a").await);
+
+    let mut keys = Vec::new();
+    for _ in 0..4 {
+        keys.push(pool.poll_for_key().await.unwrap());
+    }
+    assert_eq!(keys, ["b", "c", "b", "c"]);
+}
+
+#[tokio::test]
+async fn reliability_is_the_share_of_successes() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+    assert_eq!(pool.reliability("a").await, None);
+
+    for _ in 0..3 {
+        pool.report_success("a").await;
+    }
+    pool.report_failure("a", Duration::zero()).await;
+    assert_eq!(pool.reliability("a").await, Some(0.75));
+    pool.report_failure("a", Duration::zero()).await;
+    assert_eq!(pool.reliability("a").await, Some(0.6));
+    assert_eq!(pool.reliability("b").await, None);
+}
+
+#[tokio::test]
+async fn add_keys_adds_every_key() {
+    let pool = APIKeyPool::new();
+    pool.add_keys((0..100).map(|i| APIKey::new(&format!("k{i}"), policy())))
+        .await;
+    assert_eq!(pool.len().await, 100);
+    let keys = pool.list_keys().await;
+    assert_eq!(keys[0], "k0");
+    assert_eq!(keys[99], "k99");
+}
+
+#[tokio::test]
+async fn reset_key_restores_capacity_and_keeps_total_uses() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(3, Duration::minutes(1)),
+    ))
+    .await;
+    for _ in 0..3 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert_eq!(pool.poll_for_key().await, None);
+
+    assert!(pool.reset_key("a").await);
+    assert!(!pool.reset_key("b").await);
+    assert_eq!(pool.available_capacity().await, 3);
+    assert_eq!(pool.key_info("a").await.unwrap().total_uses, 3);
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+}
+
+#[tokio::test]
+async fn full_pool_evicts_the_unused_key() {
+    let pool = APIKeyPool::with_capacity(2);
+    pool.add_key(APIKey::new("a", policy())).await;
+    pool.add_key(APIKey::new("b", policy())).await;
+    assert_eq!(pool.acquire_any_of(&["b"]).await.as_deref(), Some("b"));
+
+    pool.add_key(APIKey::new("c", policy())).await;
+    assert_eq!(pool.list_keys().await, ["b", "c"]);
+}
+
+#[tokio::test]
+async fn clone_empty_keeps_its_own_usage() {
+    let original = APIKey::new("a", RateLimitPolicy::new(2, Duration::minutes(1)));
+    let copy = original.clone_empty("b");
+    assert_eq!(copy.key(), "b");
+    assert_eq!(copy.policy().count, 2);
+    let pool = APIKeyPool::builder()
+        .with_key(original)
+        .with_key(copy)
+        .build();
+
+    for _ in 0..2 {
+        assert_eq!(pool.acquire_any_of(&["a"]).await.as_deref(), Some("a"));
+    }
+    assert_eq!(pool.acquire_any_of(&["a"]).await, None);
+    let copy = pool.key_info("b").await.unwrap();
+    assert_eq!((copy.used_slots, copy.total_uses), (0, 0));
+    assert_eq!(pool.acquire_any_of(&["b"]).await.as_deref(), Some("b"));
+    assert_eq!(pool.key_info("a").await.unwrap().total_uses, 2);
+}
+
+#[tokio::test]
+async fn next_ready_names_the_sooner_saturated_key() {
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let pool = APIKeyPool::builder()
+        .with_clock(clock.clone())
+        .with_key(APIKey::new(
+            "sk-slow-aaaa",
+            RateLimitPolicy::new(1, Duration::seconds(30)),
+        ))
+        .with_key(APIKey::new(
+            "sk-fast-bbbb",
+            RateLimitPolicy::new(1, Duration::seconds(10)),
+        ))
+        .build();
+    pool.poll_for_key().await.unwrap();
+    clock.advance(Duration::seconds(2));
+    pool.poll_for_key().await.unwrap();
+
+    assert_eq!(
+        pool.next_ready().await,
+        Some(("…bbb".to_string(), Duration::seconds(10)))
+    );
+    clock.advance(Duration::seconds(10));
+    assert_eq!(
+        pool.next_ready().await,
+        Some(("…bbb".to_string(), Duration::zero()))
+    );
+}
+
+#[tokio::test]
+async fn for_each_key_reports_used_slots_and_counts() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "a",
+        RateLimitPolicy::new(3, Duration::minutes(1)),
+    ))
+    .await;
+    pool.add_key(APIKey::new(
+        "b",
+        RateLimitPolicy::new(5, Duration::minutes(1)),
+    ))
+    .await;
+    for _ in 0..3 {
+        pool.poll_for_key().await.unwrap();
+    }
+
+    let mut seen = Vec::new();
+    let (mut used, mut total) = (0, 0);
+    pool.for_each_key(|key, used_slots, count| {
+        seen.push((key.to_string(), used_slots, count));
+        used += used_slots;
+        total += count;
+    })
+    .await;
+    assert_eq!(seen, [("a".to_string(), 2, 3), ("b".to_string(), 1, 5)]);
+    assert_eq!((used, total), (3, 8));
+}
+
+#[tokio::test]
+async fn pools_convert_from_maps_of_policies() {
+    let policies = [("c", 3), ("a", 1), ("b", 2)].map(|(key, count)| {
+        (
+            key.to_string(),
+            RateLimitPolicy::new(count, Duration::minutes(1)),
+        )
+    });
+
+    let pool = APIKeyPool::from(HashMap::from(policies.clone()));
+    let mut keys = pool.list_keys().await;
+    keys.sort();
+    assert_eq!(keys, ["a", "b", "c"]);
+    assert_eq!(pool.policy("c").await.unwrap().count, 3);
+
+    // A B-tree map hands its keys over in order.
+    let pool = APIKeyPool::from(BTreeMap::from(policies));
+    assert_eq!(pool.list_keys().await, ["a", "b", "c"]);
+    assert_eq!(pool.available_capacity().await, 6);
+}
+
+#[tokio::test]
+async fn acquire_callback_sees_the_request_id() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new("a", policy())).await;
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let record = std::sync::Arc::clone(&seen);
+    pool.on_acquire(move |key, request_id| {
+        record
+            .lock()
+            .unwrap()
+            .push((key.to_string(), request_id.map(str::to_string)));
+    });
+
+    assert_eq!(pool.acquire_tagged("req-1").await.as_deref(), Some("a"));
+    assert_eq!(pool.poll_for_key().await.as_deref(), Some("a"));
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            ("a".to_string(), Some("req-1".to_string())),
+            ("a".to_string(), None)
+        ]
+    );
+}
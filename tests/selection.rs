@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Duration, TimeZone, Utc};
+
+use api_key_pool::*;
+
+fn policy() -> RateLimitPolicy {
+    RateLimitPolicy::new(10, Duration::seconds(1))
+}
+
+#[tokio::test]
+async fn round_robin_rotates_through_ready_keys() {
+    let pool: APIKeyPool = ["a", "b", "c"]
+        .into_iter()
+        .map(|key| APIKey::new(key, policy()))
+        .collect();
+
+    let mut keys = Vec::new();
+    for _ in 0..6 {
+        keys.push(pool.poll_for_key().await.unwrap());
+    }
+    assert_eq!(keys, ["a", "b", "c", "a", "b", "c"]);
+    assert_eq!(keys[..3].iter().collect::<HashSet<_>>().len(), 3);
+}
+
+#[tokio::test]
+async fn weighted_selection_follows_the_weights() {
+    let unlimited = RateLimitPolicy::new(10_000, Duration::hours(1));
+    let pool = APIKeyPool::builder()
+        .with_strategy(SelectionStrategy::Weighted)
+        .with_rng_seed(7)
+        .with_key(APIKey::with_weight("light", unlimited, 1))
+        .with_key(APIKey::with_weight("heavy", unlimited, 9))
+        .build();
+
+    let mut heavy = 0;
+    for _ in 0..1000 {
+        if pool.poll_for_key().await.unwrap() == "heavy" {
+            heavy += 1;
+        }
+    }
+    assert!(
+        (850..=950).contains(&heavy),
+        "heavy key chosen {heavy} times"
+    );
+}
+
+#[tokio::test]
+async fn least_recently_used_keeps_usage_balanced() {
+    let clock = MockClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+    let pool = APIKeyPool::builder()
+        .with_strategy(SelectionStrategy::LeastRecentlyUsed)
+        .with_clock(clock.clone())
+        .with_keys(["a", "b", "c"].map(|key| APIKey::new(key, policy())))
+        .build();
+
+    for _ in 0..9 {
+        pool.poll_for_key().await.unwrap();
+        clock.advance(Duration::seconds(1));
+    }
+    for (key, uses) in pool.usage_stats().await {
+        assert_eq!(uses, 3, "{key} was used {uses} times");
+    }
+}
+
+#[tokio::test]
+async fn capacity_proportional_favours_the_larger_key() {
+    let pool = APIKeyPool::builder()
+        .with_strategy(SelectionStrategy::CapacityProportional)
+        .with_key(APIKey::new(
+            "small",
+            RateLimitPolicy::new(2, Duration::hours(1)),
+        ))
+        .with_key(APIKey::new(
+            "large",
+            RateLimitPolicy::new(10, Duration::hours(1)),
+        ))
+        .build();
+
+    for _ in 0..6 {
+        pool.poll_for_key().await.unwrap();
+    }
+    assert_eq!(
+        pool.usage_stats().await,
+        [("small".to_string(), 1), ("large".to_string(), 5)]
+    );
+}
+
+#[tokio::test]
+async fn same_seed_gives_the_same_random_sequence() {
+    async fn sequence(pool: &APIKeyPool) -> Vec<String> {
+        let mut keys = Vec::new();
+        for _ in 0..20 {
+            keys.push(pool.poll_for_key().await.unwrap());
+        }
+        keys
+    }
+    let unlimited = RateLimitPolicy::new(10_000, Duration::hours(1));
+    let pool = || {
+        APIKeyPool::builder()
+            .with_strategy(SelectionStrategy::Random)
+            .with_rng_seed(11)
+            .with_key(APIKey::new("a", unlimited))
+            .with_key(APIKey::new("b", unlimited))
+            .with_key(APIKey::new("c", unlimited))
+            .build()
+    };
+
+    let first = pool();
+    let expected = sequence(&first).await;
+    assert_eq!(sequence(&pool()).await, expected);
+
+    // Reseeding a pool in use starts the sequence over.
+    first.set_rng_seed(11).await;
+    assert_eq!(sequence(&first).await, expected);
+}
+
+#[tokio::test]
+async fn random_selection_is_roughly_uniform() {
+    let unlimited = RateLimitPolicy::new(10_000, Duration::hours(1));
+    let pool = APIKeyPool::builder()
+        .with_strategy(SelectionStrategy::Random)
+        .with_rng_seed(5)
+        .with_key(APIKey::new("a", unlimited))
+        .with_key(APIKey::new("b", unlimited))
+        .with_key(APIKey::new("c", unlimited))
+        .build();
+
+    let mut counts = HashMap::new();
+    for _ in 0..3000 {
+        *counts
+            .entry(pool.poll_for_key().await.unwrap())
+            .or_insert(0) += 1;
+    }
+    for key in ["a", "b", "c"] {
+        let count = counts[key];
+        assert!((900..=1100).contains(&count), "{key} chosen {count} times");
+    }
+}
+
+#[tokio::test]
+async fn priority_key_takes_traffic_until_saturated() {
+    let pool = APIKeyPool::builder()
+        .with_strategy(SelectionStrategy::Priority)
+        .with_key(APIKey::with_priority("low", policy(), 0))
+        .with_key(APIKey::with_priority(
+            "high",
+            RateLimitPolicy::new(3, Duration::minutes(1)),
+            5,
+        ))
+        .build();
+
+    let mut keys = Vec::new();
+    for _ in 0..5 {
+        keys.push(pool.poll_for_key().await.unwrap());
+    }
+    assert_eq!(keys, ["high", "high", "high", "low", "low"]);
+}
+
+#[tokio::test]
+async fn smooth_weighted_interleaves_by_weight() {
+    let unlimited = RateLimitPolicy::new(10_000, Duration::hours(1));
+    let pool = APIKeyPool::builder()
+        .with_strategy(SelectionStrategy::SmoothWeighted)
+        .with_key(APIKey::with_weight("a", unlimited, 3))
+        .with_key(APIKey::with_weight("b", unlimited, 1))
+        .build();
+
+    let mut keys = Vec::new();
+    for _ in 0..8 {
+        keys.push(pool.poll_for_key().await.unwrap());
+    }
+    assert_eq!(keys, ["a", "a", "b", "a", "a", "a", "b", "a"]);
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "serde")]
+
+use chrono::Duration;
+
+use api_key_pool::*;
+
+#[tokio::test]
+async fn key_configs_round_trip_into_a_pool() {
+    let configs = vec![
+        KeyConfig {
+            key: "a".to_string(),
+            count: 5,
+            per_seconds: 60,
+        },
+        KeyConfig {
+            key: "b".to_string(),
+            count: 1,
+            per_seconds: 2,
+        },
+    ];
+    let json = serde_json::to_string(&configs).unwrap();
+    let parsed: Vec<KeyConfig> = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, configs);
+
+    let keys: Vec<APIKey> = parsed
+        .into_iter()
+        .map(|config| APIKey::try_from(config).unwrap())
+        .collect();
+    let pool: APIKeyPool = keys.into_iter().collect();
+    assert_eq!(pool.list_keys().await, ["a", "b"]);
+    let policy = pool.policy("a").await.unwrap();
+    assert_eq!((policy.count, policy.per), (5, Duration::seconds(60)));
+    let policy = pool.policy("b").await.unwrap();
+    assert_eq!((policy.count, policy.per), (1, Duration::seconds(2)));
+}
+
+#[test]
+fn policies_round_trip_through_json() {
+    let policy = RateLimitPolicy::token_bucket(10, Duration::minutes(1), 20);
+    let json = serde_json::to_string(&policy).unwrap();
+    let parsed: RateLimitPolicy = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.count, 10);
+    assert_eq!(parsed.per, Duration::minutes(1));
+    assert_eq!(
+        parsed.algorithm,
+        RateLimitAlgorithm::TokenBucket { burst: 20 }
+    );
+}
+
+#[test]
+fn sub_second_policies_are_not_serialized() {
+    let policy: RateLimitPolicy = "1/500ms".parse().unwrap();
+    assert!(serde_json::to_string(&policy).is_err());
+}
+
+#[test]
+fn invalid_policies_are_not_deserialized() {
+    for json in [
+        r#"{"count":0,"per":1}"#,
+        r#"{"count":1,"per":0}"#,
+        r#"{"count":1,"per":-5}"#,
+        r#"{"count":1,"per":1,"algorithm":{"TokenBucket":{"burst":0}}}"#,
+        r#"{"count":1,"per":1,"algorithm":{"LeakyBucket":{"capacity":0}}}"#,
+    ] {
+        assert!(
+            serde_json::from_str::<RateLimitPolicy>(json).is_err(),
+            "{json}"
+        );
+    }
+}
+
+#[test]
+fn invalid_key_configs_are_rejected() {
+    let config = KeyConfig {
+        key: "a".to_string(),
+        count: 0,
+        per_seconds: 60,
+    };
+    assert_eq!(
+        APIKey::try_from(config).unwrap_err(),
+        PolicyError::ZeroCount
+    );
+    let config = KeyConfig {
+        key: "a".to_string(),
+        count: 1,
+        per_seconds: -1,
+    };
+    assert_eq!(
+        APIKey::try_from(config).unwrap_err(),
+        PolicyError::NonPositivePer
+    );
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "stream")]
+
+use std::time::Duration as StdDuration;
+
+use chrono::Duration;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+
+use api_key_pool::*;
+
+#[tokio::test(start_paused = true)]
+async fn key_stream_yields_at_the_policy_cadence() {
+    let pool = APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_key(APIKey::new(
+            "a",
+            RateLimitPolicy::new(1, Duration::seconds(2)),
+        ))
+        .build();
+    let start = Instant::now();
+    let stream = pool.into_key_stream();
+    tokio::pin!(stream);
+
+    for expected in [0, 2, 4, 6] {
+        assert_eq!(stream.next().await.as_deref(), Some("a"));
+        let elapsed = start.elapsed();
+        let expected = StdDuration::from_secs(expected);
+        assert!(
+            elapsed >= expected && elapsed <= expected + StdDuration::from_millis(5),
+            "expected a key at {expected:?}, got one at {elapsed:?}"
+        );
+    }
+}
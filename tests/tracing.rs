@@ -0,0 +1,25 @@
+#![cfg(feature = "tracing")]
+
+use chrono::Duration;
+use tracing_test::traced_test;
+
+use api_key_pool::*;
+
+#[tokio::test]
+#[traced_test]
+async fn saturated_pool_logs_that_all_keys_are_busy() {
+    let pool = APIKeyPool::new();
+    pool.add_key(APIKey::new(
+        "sk-live-0123456789abcdef",
+        RateLimitPolicy::new(1, Duration::minutes(1)),
+    ))
+    .await;
+    pool.poll_for_key().await.unwrap();
+    assert!(logs_contain("acquired API key"));
+    assert!(!logs_contain("all API keys are busy"));
+
+    assert_eq!(pool.poll_for_key().await, None);
+    assert!(logs_contain("all API keys are busy"));
+    assert!(logs_contain("retry_after_ms="));
+    assert!(!logs_contain("sk-live-0123456789abcdef"));
+}
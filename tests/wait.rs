@@ -0,0 +1,312 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration as StdDuration;
+
+use chrono::Duration;
+use tokio::time::Instant;
+
+use api_key_pool::*;
+
+/// Returns a pool on the runtime's timer holding a single API key.
+fn paused_pool(policy: RateLimitPolicy) -> APIKeyPool {
+    APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_key(APIKey::new("a", policy))
+        .build()
+}
+
+/// Asserts that `elapsed` is `expected`, give or take the timer's millisecond resolution.
+fn assert_about(elapsed: StdDuration, expected: StdDuration) {
+    assert!(
+        elapsed >= expected && elapsed <= expected + StdDuration::from_millis(5),
+        "expected about {expected:?}, waited {elapsed:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn wait_for_key_wakes_at_window_expiry() {
+    let pool = paused_pool(RateLimitPolicy::new(1, Duration::seconds(5)));
+    pool.try_acquire().await.unwrap();
+
+    let start = Instant::now();
+    assert_eq!(pool.wait_for_key().await, Some("a".to_string()));
+    assert_about(start.elapsed(), StdDuration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn acquire_timeout_gives_up_before_the_window_frees() {
+    let pool = paused_pool(RateLimitPolicy::new(1, Duration::seconds(10)));
+    pool.try_acquire().await.unwrap();
+
+    let start = Instant::now();
+    assert_eq!(pool.acquire_timeout(Duration::seconds(1)).await, None);
+    assert_about(start.elapsed(), StdDuration::from_secs(1));
+
+    assert_eq!(
+        pool.acquire_timeout(Duration::seconds(9)).await,
+        Some("a".to_string())
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn fixed_window_resets_fully_at_the_boundary() {
+    let pool = paused_pool(RateLimitPolicy::fixed_window(3, Duration::seconds(1)));
+    for _ in 0..3 {
+        pool.try_acquire().await.unwrap();
+    }
+    let Err(PoolError::AllBusy { retry_after }) = pool.try_acquire().await else {
+        panic!("the fixed window should be used up");
+    };
+    assert!(retry_after <= Duration::seconds(1));
+
+    let start = Instant::now();
+    assert_eq!(pool.wait_for_key().await, Some("a".to_string()));
+    assert_about(start.elapsed(), retry_after.to_std().unwrap());
+    // The other two slots of the new window are free as well.
+    assert_eq!(pool.available_capacity().await, 2);
+}
+
+/// Returns how long each of several waits for a used-up API key took.
+async fn wait_times(pool: &APIKeyPool) -> Vec<StdDuration> {
+    let mut waits = Vec::new();
+    pool.try_acquire().await.unwrap();
+    for _ in 0..5 {
+        let start = Instant::now();
+        pool.wait_for_key().await.unwrap();
+        waits.push(start.elapsed());
+    }
+    waits
+}
+
+#[tokio::test(start_paused = true)]
+async fn jitter_spreads_out_sleeps() {
+    let policy = RateLimitPolicy::new(1, Duration::seconds(1));
+    let steady = paused_pool(policy);
+    for wait in wait_times(&steady).await {
+        assert_about(wait, StdDuration::from_secs(1));
+    }
+
+    let jittered = APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_rng_seed(3)
+        .with_key(APIKey::new("a", policy))
+        .build();
+    jittered.set_jitter(Duration::milliseconds(500)).await;
+    let waits = wait_times(&jittered).await;
+    for wait in &waits {
+        assert!(*wait >= StdDuration::from_secs(1) && *wait <= StdDuration::from_millis(1505));
+    }
+    let mut distinct = waits.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert!(distinct.len() > 1, "{waits:?}");
+}
+
+#[tokio::test(start_paused = true)]
+async fn leaky_bucket_spaces_out_back_to_back_requests() {
+    let pool = paused_pool(RateLimitPolicy::leaky_bucket(10, Duration::seconds(1), 1));
+    let start = Instant::now();
+    let mut admitted = Vec::new();
+    for _ in 0..5 {
+        pool.wait_for_key().await.unwrap();
+        admitted.push(start.elapsed());
+        assert_eq!(
+            pool.try_acquire().await,
+            Err(PoolError::AllBusy {
+                retry_after: Duration::milliseconds(100)
+            })
+        );
+    }
+    for pair in admitted.windows(2) {
+        assert!(
+            pair[1] - pair[0] >= StdDuration::from_millis(100),
+            "{admitted:?}"
+        );
+    }
+    assert_about(admitted[4], StdDuration::from_millis(400));
+}
+
+#[tokio::test(start_paused = true)]
+async fn backoff_grows_each_sleep_by_the_factor() {
+    let pool = paused_pool(RateLimitPolicy::new(10, Duration::seconds(1)));
+    pool.set_backoff(Backoff::new(
+        Duration::milliseconds(100),
+        2.0,
+        Duration::seconds(10),
+    ))
+    .await;
+    let checks = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&checks);
+    pool.set_health_check(move |_| {
+        let mut seen = seen.lock().unwrap();
+        seen.push(Instant::now());
+        seen.len() > 4
+    })
+    .await;
+
+    pool.wait_for_key().await.unwrap();
+    let checks = checks.lock().unwrap();
+    // Each sleep is the recheck interval of 100ms plus the backoff, which doubles.
+    let sleeps: Vec<_> = checks.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    assert_eq!(sleeps.len(), 4);
+    for (sleep, expected) in sleeps.into_iter().zip([200, 300, 500, 900]) {
+        assert_about(sleep, StdDuration::from_millis(expected));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn shutdown_wakes_a_waiter_on_a_saturated_pool() {
+    let pool = paused_pool(RateLimitPolicy::new(1, Duration::hours(1)));
+    pool.try_acquire().await.unwrap();
+
+    let start = Instant::now();
+    let waiter = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.wait_for_key().await }
+    });
+    let timed = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.acquire_timeout(Duration::minutes(30)).await }
+    });
+    tokio::time::sleep(StdDuration::from_secs(1)).await;
+    pool.shutdown();
+
+    assert_eq!(waiter.await.unwrap(), None);
+    assert_eq!(timed.await.unwrap(), None);
+    assert_about(start.elapsed(), StdDuration::from_secs(1));
+    assert_eq!(pool.try_acquire().await, Err(PoolError::ShutDown));
+}
+
+#[tokio::test(start_paused = true)]
+async fn waiter_wakes_at_the_boundary_or_when_notified() {
+    let pool = paused_pool(RateLimitPolicy::new(1, Duration::seconds(5)));
+    pool.try_acquire().await.unwrap();
+
+    let start = Instant::now();
+    let waiter = tokio::spawn({
+        let pool = pool.clone();
+        async move { (pool.wait_for_key().await, Instant::now()) }
+    });
+    let (key, woke) = waiter.await.unwrap();
+    assert_eq!(key.as_deref(), Some("a"));
+    assert_about(woke - start, StdDuration::from_secs(5));
+
+    // A key added partway through the window wakes the waiter right away.
+    let start = Instant::now();
+    let waiter = tokio::spawn({
+        let pool = pool.clone();
+        async move { (pool.wait_for_key().await, Instant::now()) }
+    });
+    tokio::time::sleep(StdDuration::from_secs(2)).await;
+    pool.add_key(APIKey::new(
+        "b",
+        RateLimitPolicy::new(1, Duration::seconds(5)),
+    ))
+    .await;
+    let (key, woke) = waiter.await.unwrap();
+    assert_eq!(key.as_deref(), Some("b"));
+    assert_about(woke - start, StdDuration::from_secs(2));
+}
+
+#[tokio::test(start_paused = true)]
+async fn ramp_grows_the_throughput_of_a_warming_key() {
+    let pool = APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_key(APIKey::with_ramp(
+            "a",
+            RateLimitPolicy::new(10, Duration::seconds(10)),
+            1,
+            Duration::seconds(10),
+        ))
+        .build();
+
+    // Nothing leaves the window during the ramp, so the uses so far are the count so far.
+    let mut used = 0;
+    for second in 0..10 {
+        while pool.try_acquire().await.is_ok() {
+            used += 1;
+        }
+        assert_eq!(used, 1 + 9 * second / 10, "after {second}s");
+        tokio::time::sleep(StdDuration::from_secs(1)).await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn ramp_towards_a_zero_count_allows_one_use() {
+    let pool = APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_key(APIKey::with_ramp(
+            "a",
+            RateLimitPolicy {
+                count: 0,
+                per: Duration::seconds(10),
+                algorithm: RateLimitAlgorithm::SlidingWindow,
+            },
+            3,
+            Duration::seconds(10),
+        ))
+        .build();
+    assert_eq!(pool.try_acquire().await.as_deref(), Ok("a"));
+    assert!(pool.try_acquire().await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn warming_key_is_found_before_a_sooner_looking_key() {
+    let pool = APIKeyPool::builder()
+        .with_clock(TokioClock::new())
+        .with_key(APIKey::with_ramp(
+            "a",
+            RateLimitPolicy::new(10, Duration::seconds(10)),
+            1,
+            Duration::seconds(10),
+        ))
+        .with_key(APIKey::new(
+            "b",
+            RateLimitPolicy::new(1, Duration::seconds(7)),
+        ))
+        .build();
+    assert_eq!(pool.try_acquire().await.as_deref(), Ok("a"));
+    assert_eq!(pool.try_acquire().await.as_deref(), Ok("b"));
+
+    // Halfway through its ramp, `a` allows five uses, though at its first count it would be busy
+    // for longer than `b`.
+    tokio::time::sleep(StdDuration::from_secs(5)).await;
+    assert_eq!(pool.try_acquire().await.as_deref(), Ok("a"));
+    for _ in 0..3 {
+        assert_eq!(pool.try_acquire().await.as_deref(), Ok("a"));
+    }
+    assert!(pool.try_acquire().await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn percentiles_of_known_waits() {
+    let pool = paused_pool(RateLimitPolicy::new(1, Duration::seconds(10)));
+    assert_eq!(
+        pool.wait_latency_percentiles().await,
+        (Duration::zero(), Duration::zero(), Duration::zero())
+    );
+
+    // Waits of 1 to 10 seconds, in a shuffled order.
+    pool.try_acquire().await.unwrap();
+    for wait in [7, 2, 10, 4, 1, 9, 5, 3, 8, 6] {
+        tokio::time::sleep(StdDuration::from_secs(10 - wait)).await;
+        pool.wait_for_key().await.unwrap();
+    }
+    assert_eq!(
+        pool.wait_latency_percentiles().await,
+        (
+            Duration::seconds(5),
+            Duration::seconds(9),
+            Duration::seconds(10)
+        )
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn next_window_acquire_succeeds_at_the_boundary() {
+    let pool = paused_pool(RateLimitPolicy::new(1, Duration::seconds(3)));
+    pool.try_acquire().await.unwrap();
+
+    let start = Instant::now();
+    assert_eq!(pool.acquire_next_window().await.as_deref(), Some("a"));
+    assert_about(start.elapsed(), StdDuration::from_secs(3));
+}